@@ -1,8 +1,13 @@
 use std::path::PathBuf;
 
-use clap::{Args, Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 
-use crate::daemon::DaemonStartArgs;
+use crate::{
+    daemon::{DaemonAction, DaemonStartArgs, DaemonStopArgs, StatusKind},
+    export::ConflictStrategy,
+    secrets::{parse_secret_env, SecretEnv},
+    task::RestartPolicy,
+};
 
 #[derive(Parser)]
 #[clap(author, version)]
@@ -13,6 +18,13 @@ pub struct Cmd {
     #[clap(short, long, help = "Path to custom data directory")]
     pub custom_data_dir: Option<PathBuf>,
 
+    #[clap(
+        short,
+        long,
+        help = "Use a profile, with its own daemon and tasks (default: BJOBS_PROFILE env var, unless a custom data directory is provided)"
+    )]
+    pub profile: Option<String>,
+
     #[clap(subcommand)]
     pub action: Action,
 }
@@ -20,7 +32,13 @@ pub struct Cmd {
 #[derive(Subcommand)]
 pub enum Action {
     #[clap(about = "List all tasks")]
-    List,
+    List(ListArgs),
+
+    #[clap(about = "Open an interactive dashboard")]
+    Top,
+
+    #[clap(about = "Show details about a task")]
+    Show(ShowArgs),
 
     #[clap(about = "Check if any task failed")]
     Check(CheckArgs),
@@ -28,6 +46,15 @@ pub enum Action {
     #[clap(about = "Start a task")]
     Run(RunArgs),
 
+    #[clap(about = "Make the daemon's tasks match a task file")]
+    Apply(ApplyArgs),
+
+    #[clap(about = "Export all tasks as JSON")]
+    Export(ExportArgs),
+
+    #[clap(about = "Import tasks from an export")]
+    Import(ImportArgs),
+
     #[clap(about = "Stop a task")]
     Kill(KillArgs),
 
@@ -37,26 +64,154 @@ pub enum Action {
     #[clap(about = "Remove a task")]
     Remove(RemoveArgs),
 
+    #[clap(about = "Wait for tasks to complete")]
+    Wait(WaitArgs),
+
     #[clap(about = "Start the daemon")]
     Start(DaemonStartArgs),
 
     #[clap(about = "Check the daemon's status")]
     Status,
 
+    #[clap(about = "List the profiles and their daemon's status")]
+    Profiles,
+
     #[clap(about = "Stop the daemon")]
-    Stop,
+    Stop(DaemonStopArgs),
+
+    #[clap(subcommand, about = "Manage the daemon")]
+    Daemon(DaemonAction),
+
+    #[clap(subcommand, about = "Manage the configuration")]
+    Config(ConfigAction),
 
     #[clap(about = "Display the logs")]
     Logs(LogsArgs),
+
+    #[clap(about = "Display the calls that changed the daemon's state")]
+    Audit(AuditArgs),
+}
+
+#[derive(Args)]
+pub struct ListArgs {
+    #[clap(
+        short,
+        long = "status",
+        value_delimiter = ',',
+        help = "Only list tasks with one of the provided statuses"
+    )]
+    pub statuses: Vec<StatusKind>,
+
+    #[clap(
+        short,
+        long = "name",
+        help = "Only list tasks whose name matches the provided glob pattern"
+    )]
+    pub names: Vec<String>,
+
+    #[clap(short, long = "tag", help = "Only list tasks with the provided tag")]
+    pub tags: Vec<String>,
+
+    #[clap(long, value_enum, default_value = "name", help = "Sort tasks")]
+    pub sort: SortBy,
+
+    #[clap(
+        long,
+        value_delimiter = ',',
+        default_value = "name,status,shell,cmd",
+        help = "Columns to display"
+    )]
+    pub columns: Vec<Column>,
+
+    #[clap(short, long, help = "Redraw the list periodically")]
+    pub watch: bool,
+
+    #[clap(
+        long,
+        default_value = "2",
+        requires = "watch",
+        help = "Interval between redraws, in seconds"
+    )]
+    pub interval: u64,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum SortBy {
+    /// Alphabetical order
+    Name,
+    /// Oldest started first
+    Started,
+    /// Longest running first
+    Duration,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Column {
+    Name,
+    Status,
+    Shell,
+    Cmd,
+    Tags,
+    Pid,
+    Started,
+    Duration,
+    Restarts,
+}
+
+#[derive(Args)]
+pub struct ShowArgs {
+    #[clap(help = "Name of the task")]
+    pub name: String,
+
+    #[clap(
+        short = 'n',
+        long,
+        default_value = "10",
+        help = "Number of log lines to show"
+    )]
+    pub lines: usize,
+
+    #[clap(long, help = "Output as JSON")]
+    pub json: bool,
 }
 
 #[derive(Args)]
 pub struct CheckArgs {
+    #[clap(help = "Only check tasks whose name matches one of the provided glob patterns")]
+    pub names: Vec<String>,
+
+    #[clap(short, long = "tag", help = "Only check tasks with the provided tag")]
+    pub tags: Vec<String>,
+
     #[clap(long, help = "Report succeeded tasks as well")]
     pub succeeded: bool,
 
-    #[clap(long, help = "Don't display messages outside of errors")]
+    #[clap(long, help = "Don't display any message")]
     pub silent: bool,
+
+    #[clap(
+        long,
+        default_value = "1",
+        help = "Exit code to use when at least one task failed"
+    )]
+    pub failed_code: i32,
+
+    #[clap(
+        long,
+        default_value = "2",
+        help = "Exit code to use when no task failed but some are still running"
+    )]
+    pub running_code: i32,
+
+    #[clap(
+        long,
+        help = "Acknowledge reported failures so they aren't reported again",
+        conflicts_with = "clear"
+    )]
+    pub ack: bool,
+
+    #[clap(long, help = "Remove reported failed tasks")]
+    pub clear: bool,
 }
 
 #[derive(Args)]
@@ -73,6 +228,51 @@ pub struct RunArgs {
     #[clap(short, long, help = "Start directory")]
     pub start_dir: Option<PathBuf>,
 
+    #[clap(short, long = "tag", help = "Add a tag to the task")]
+    pub tags: Vec<String>,
+
+    #[clap(short, long = "env", value_parser = parse_env_var, help = "Set an environment variable (KEY=VALUE)")]
+    pub env: Vec<(String, String)>,
+
+    #[clap(
+        long = "secret-env",
+        value_parser = parse_secret_env,
+        help = "Set a secret environment variable, redacted from the output and listings (NAME to prompt for its value, NAME=FILE to read it from a file)"
+    )]
+    pub secret_env: Vec<SecretEnv>,
+
+    #[clap(
+        long,
+        value_enum,
+        default_value = "never",
+        help = "When to restart the task automatically"
+    )]
+    pub restart: RestartPolicy,
+
+    #[clap(
+        long,
+        help = "Wait for another task to be running or to have succeeded before starting"
+    )]
+    pub depends_on: Vec<String>,
+
+    #[clap(long, help = "Keep the task running when the daemon stops")]
+    pub keep_running: bool,
+
+    #[clap(long, help = "Command to run when the task starts")]
+    pub on_start: Option<String>,
+
+    #[clap(long, help = "Command to run when the task succeeds")]
+    pub on_success: Option<String>,
+
+    #[clap(long, help = "Command to run when the task fails")]
+    pub on_failure: Option<String>,
+
+    #[clap(
+        long,
+        help = "Seconds after which the task's hooks are killed, overriding the daemon's setting (0 to disable)"
+    )]
+    pub hook_timeout: Option<u64>,
+
     #[clap(short, long, help = "Ignore identical commands")]
     pub ignore_identicals: bool,
 
@@ -83,6 +283,44 @@ pub struct RunArgs {
     pub silent: bool,
 }
 
+#[derive(Args)]
+pub struct ApplyArgs {
+    #[clap(
+        default_value = "bjobs.toml",
+        help = "Path to the task file (tasks start in its directory by default)"
+    )]
+    pub file: PathBuf,
+
+    #[clap(long, help = "Remove the tasks that aren't declared in the file")]
+    pub prune: bool,
+
+    #[clap(long, help = "Only display the changes that would be made")]
+    pub dry_run: bool,
+}
+
+#[derive(Args)]
+pub struct ExportArgs {
+    #[clap(long, help = "Include the tasks' last status")]
+    pub with_status: bool,
+
+    #[clap(long, help = "Include the tasks' status and logs")]
+    pub with_logs: bool,
+}
+
+#[derive(Args)]
+pub struct ImportArgs {
+    #[clap(help = "Path to the export file ('-' to read from STDIN)")]
+    pub file: PathBuf,
+
+    #[clap(
+        long,
+        value_enum,
+        default_value = "skip",
+        help = "What to do when a task with the same name already exists"
+    )]
+    pub on_conflict: ConflictStrategy,
+}
+
 #[derive(Args)]
 pub struct KillArgs {
     #[clap(help = "Name of the task to kill")]
@@ -101,6 +339,32 @@ pub struct RemoveArgs {
     pub name: String,
 }
 
+#[derive(Args)]
+pub struct WaitArgs {
+    #[clap(required = true, help = "Name of the tasks to wait for")]
+    pub names: Vec<String>,
+
+    #[clap(
+        long,
+        help = "Maximum number of seconds to wait for, after which tasks still running are reported"
+    )]
+    pub timeout: Option<u64>,
+
+    #[clap(
+        long,
+        default_value = "1",
+        help = "Exit code to use when at least one task failed"
+    )]
+    pub failed_code: i32,
+
+    #[clap(
+        long,
+        default_value = "2",
+        help = "Exit code to use when no task failed but some are still running"
+    )]
+    pub running_code: i32,
+}
+
 #[derive(Args)]
 pub struct LogsArgs {
     #[clap(help = "The task to show the logs of")]
@@ -111,7 +375,14 @@ pub struct LogsArgs {
 
     #[clap(
         long,
-        help = "Use an alternative pager (default: PAGER env var, or 'less')"
+        requires = "task_name",
+        help = "Show the output of the task's hooks instead"
+    )]
+    pub hooks: bool,
+
+    #[clap(
+        long,
+        help = "Use an alternative pager (default: PAGER env var, configuration file, or 'less')"
     )]
     pub pager: Option<String>,
 
@@ -121,3 +392,46 @@ pub struct LogsArgs {
     )]
     pub no_less_options: bool,
 }
+
+#[derive(Args)]
+pub struct AuditArgs {
+    #[clap(short, long, help = "Only show calls about the provided task")]
+    pub task: Option<String>,
+
+    #[clap(long, help = "Only show calls to the provided function (e.g. 'kill')")]
+    pub call: Option<String>,
+
+    #[clap(
+        short,
+        long,
+        help = "Only show calls made by the provided user name or UID"
+    )]
+    pub user: Option<String>,
+
+    #[clap(
+        short = 'n',
+        long,
+        help = "Only show the provided number of most recent calls"
+    )]
+    pub last: Option<usize>,
+
+    #[clap(long, help = "Output as JSON lines")]
+    pub json: bool,
+
+    #[clap(long, help = "Check that no entry was modified or removed")]
+    pub verify: bool,
+}
+
+#[derive(Subcommand)]
+pub enum ConfigAction {
+    #[clap(about = "Show the effective configuration and where each value comes from")]
+    Show,
+}
+
+fn parse_env_var(input: &str) -> Result<(String, String), String> {
+    let (key, value) = input
+        .split_once('=')
+        .ok_or("Expected an environment variable in KEY=VALUE format")?;
+
+    Ok((key.to_owned(), value.to_owned()))
+}
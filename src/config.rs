@@ -0,0 +1,419 @@
+use std::{
+    env,
+    fmt::{self, Display},
+    fs,
+    path::PathBuf,
+};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+pub static DEFAULT_SHELL_CMD: &str = "/bin/sh -c";
+pub static DEFAULT_PAGER: &str = "less";
+pub static DEFAULT_CONNECT_TIMEOUT: u64 = 5;
+pub static DEFAULT_REQUEST_TIMEOUT: u64 = 30;
+pub static DEFAULT_HOOK_TIMEOUT: u64 = 60;
+
+/// Content of the configuration file
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    data_dir: Option<PathBuf>,
+    default_shell: Option<String>,
+    log_retention: Option<usize>,
+    max_concurrent: Option<usize>,
+    autostart: Option<bool>,
+    timestamp_format: Option<String>,
+    pager: Option<String>,
+    connect_timeout: Option<u64>,
+    request_timeout: Option<u64>,
+    http_listen: Option<String>,
+    http_allow_origin: Option<String>,
+    full_access: Option<Vec<String>>,
+    read_only_access: Option<Vec<String>>,
+    on_start: Option<String>,
+    on_success: Option<String>,
+    on_failure: Option<String>,
+    hook_timeout: Option<u64>,
+}
+
+/// Effective configuration
+///
+/// Each value comes from, by order of precedence: the command line, the environment, the configuration file, or the defaults
+#[derive(Clone)]
+pub struct Config {
+    pub path: PathBuf,
+    pub path_exists: bool,
+    pub data_dir: Setting<PathBuf>,
+    pub default_shell: Setting<String>,
+    /// Maximum number of output lines kept in memory for each task
+    pub log_retention: Setting<Option<usize>>,
+    /// Maximum number of tasks running at the same time
+    pub max_concurrent: Setting<Option<usize>>,
+    /// Start the daemon when a command needs it
+    pub autostart: Setting<bool>,
+    pub timestamp_format: Setting<Option<String>>,
+    pub pager: Setting<String>,
+    /// Seconds to wait for the daemon to accept a connection
+    pub connect_timeout: Setting<u64>,
+    /// Seconds to wait for the daemon to answer a request, if limited
+    pub request_timeout: Setting<Option<u64>>,
+    /// Address of the daemon's HTTP API: `unix:<path>` or a loopback `<host>:<port>`
+    pub http_listen: Setting<Option<String>>,
+    /// Origin of the web pages allowed to call the HTTP API, which refuses cross-origin requests otherwise
+    pub http_allow_origin: Setting<Option<String>>,
+    /// Users (by name or UID) and groups (`@` followed by a name or GID) allowed to use the daemon besides its owner
+    pub full_access: Setting<Vec<String>>,
+    /// Users and groups allowed to query the daemon without changing anything
+    pub read_only_access: Setting<Vec<String>>,
+    /// Commands run for every task when it starts, succeeds or fails, after the task's own hooks
+    pub on_start: Setting<Option<String>>,
+    pub on_success: Setting<Option<String>>,
+    pub on_failure: Setting<Option<String>>,
+    /// Seconds after which a hook is killed, if limited
+    pub hook_timeout: Setting<Option<u64>>,
+}
+
+#[derive(Clone)]
+pub struct Setting<T> {
+    pub value: T,
+    pub source: Source,
+}
+
+#[derive(Clone, Copy)]
+pub enum Source {
+    Default,
+    File,
+    Env,
+    Cli,
+}
+
+impl Config {
+    /// Load the configuration again, keeping the values provided on the command line
+    pub fn reload(&self) -> Result<Self> {
+        let mut config = Self::load()?;
+
+        if let Source::Cli = self.data_dir.source {
+            config.data_dir = self.data_dir.clone();
+        }
+
+        Ok(config)
+    }
+
+    /// Get the name of the settings that differ between two configurations
+    pub fn diff(&self, other: &Config) -> Vec<&'static str> {
+        let mut changes = vec![];
+
+        if self.data_dir.value != other.data_dir.value {
+            changes.push("data_dir");
+        }
+
+        if self.default_shell.value != other.default_shell.value {
+            changes.push("default_shell");
+        }
+
+        if self.log_retention.value != other.log_retention.value {
+            changes.push("log_retention");
+        }
+
+        if self.max_concurrent.value != other.max_concurrent.value {
+            changes.push("max_concurrent");
+        }
+
+        if self.autostart.value != other.autostart.value {
+            changes.push("autostart");
+        }
+
+        if self.timestamp_format.value != other.timestamp_format.value {
+            changes.push("timestamp_format");
+        }
+
+        if self.pager.value != other.pager.value {
+            changes.push("pager");
+        }
+
+        if self.connect_timeout.value != other.connect_timeout.value {
+            changes.push("connect_timeout");
+        }
+
+        if self.request_timeout.value != other.request_timeout.value {
+            changes.push("request_timeout");
+        }
+
+        if self.http_listen.value != other.http_listen.value {
+            changes.push("http_listen");
+        }
+
+        if self.http_allow_origin.value != other.http_allow_origin.value {
+            changes.push("http_allow_origin");
+        }
+
+        if self.full_access.value != other.full_access.value {
+            changes.push("full_access");
+        }
+
+        if self.read_only_access.value != other.read_only_access.value {
+            changes.push("read_only_access");
+        }
+
+        if self.on_start.value != other.on_start.value {
+            changes.push("on_start");
+        }
+
+        if self.on_success.value != other.on_success.value {
+            changes.push("on_success");
+        }
+
+        if self.on_failure.value != other.on_failure.value {
+            changes.push("on_failure");
+        }
+
+        if self.hook_timeout.value != other.hook_timeout.value {
+            changes.push("hook_timeout");
+        }
+
+        changes
+    }
+
+    pub fn load() -> Result<Self> {
+        let path = match env::var_os("BJOBS_CONFIG") {
+            Some(path) => PathBuf::from(path),
+            None => dirs::config_dir()
+                .context("Failed to get path to the configuration directory")?
+                .join("bjobs")
+                .join("config.toml"),
+        };
+
+        let path_exists = path.exists();
+
+        let file = if path_exists {
+            let content = fs::read_to_string(&path).with_context(|| {
+                format!("Failed to read configuration file at '{}'", path.display())
+            })?;
+
+            toml::from_str::<ConfigFile>(&content).with_context(|| {
+                format!("Failed to parse configuration file at '{}'", path.display())
+            })?
+        } else {
+            ConfigFile::default()
+        };
+
+        let default_data_dir = dirs::data_local_dir()
+            .context("Failed to get path to local data directory")?
+            .join("bjobs");
+
+        Ok(Self {
+            data_dir: resolve(
+                "BJOBS_DATA_DIR",
+                |value| Ok(PathBuf::from(value)),
+                file.data_dir,
+                default_data_dir,
+            )?,
+            default_shell: resolve(
+                "BJOBS_DEFAULT_SHELL",
+                |value| Ok(value.to_owned()),
+                file.default_shell,
+                DEFAULT_SHELL_CMD.to_owned(),
+            )?,
+            log_retention: resolve(
+                "BJOBS_LOG_RETENTION",
+                |value| Ok(Some(value.parse()?)),
+                file.log_retention.map(Some),
+                None,
+            )?,
+            max_concurrent: resolve(
+                "BJOBS_MAX_CONCURRENT",
+                |value| Ok(Some(value.parse()?)),
+                file.max_concurrent.map(Some),
+                None,
+            )?,
+            autostart: resolve(
+                "BJOBS_AUTOSTART",
+                |value| Ok(matches!(value, "1" | "true" | "yes")),
+                file.autostart,
+                false,
+            )?,
+            timestamp_format: resolve(
+                "BJOBS_TIMESTAMP_FORMAT",
+                |value| Ok(Some(value.to_owned())),
+                file.timestamp_format.map(Some),
+                None,
+            )?,
+            pager: resolve(
+                "PAGER",
+                |value| Ok(value.to_owned()),
+                file.pager,
+                DEFAULT_PAGER.to_owned(),
+            )?,
+            connect_timeout: resolve(
+                "BJOBS_CONNECT_TIMEOUT",
+                |value| Ok(value.parse()?),
+                file.connect_timeout,
+                DEFAULT_CONNECT_TIMEOUT,
+            )?,
+            // A value of 0 disables the timeout
+            request_timeout: resolve(
+                "BJOBS_REQUEST_TIMEOUT",
+                |value| Ok(Some(value.parse()?).filter(|secs| *secs > 0)),
+                file.request_timeout
+                    .map(|secs| Some(secs).filter(|secs| *secs > 0)),
+                Some(DEFAULT_REQUEST_TIMEOUT),
+            )?,
+            http_listen: resolve(
+                "BJOBS_HTTP_LISTEN",
+                |value| Ok(Some(value.to_owned()).filter(|value| !value.is_empty())),
+                file.http_listen.map(Some),
+                None,
+            )?,
+            http_allow_origin: resolve(
+                "BJOBS_HTTP_ALLOW_ORIGIN",
+                |value| Ok(Some(value.to_owned()).filter(|value| !value.is_empty())),
+                file.http_allow_origin.map(Some),
+                None,
+            )?,
+            full_access: resolve(
+                "BJOBS_FULL_ACCESS",
+                |value| Ok(parse_list(value)),
+                file.full_access,
+                vec![],
+            )?,
+            read_only_access: resolve(
+                "BJOBS_READ_ONLY_ACCESS",
+                |value| Ok(parse_list(value)),
+                file.read_only_access,
+                vec![],
+            )?,
+            on_start: resolve(
+                "BJOBS_ON_START",
+                |value| Ok(Some(value.to_owned()).filter(|value| !value.is_empty())),
+                file.on_start.map(Some),
+                None,
+            )?,
+            on_success: resolve(
+                "BJOBS_ON_SUCCESS",
+                |value| Ok(Some(value.to_owned()).filter(|value| !value.is_empty())),
+                file.on_success.map(Some),
+                None,
+            )?,
+            on_failure: resolve(
+                "BJOBS_ON_FAILURE",
+                |value| Ok(Some(value.to_owned()).filter(|value| !value.is_empty())),
+                file.on_failure.map(Some),
+                None,
+            )?,
+            // A value of 0 disables the timeout
+            hook_timeout: resolve(
+                "BJOBS_HOOK_TIMEOUT",
+                |value| Ok(Some(value.parse()?).filter(|secs| *secs > 0)),
+                file.hook_timeout
+                    .map(|secs| Some(secs).filter(|secs| *secs > 0)),
+                Some(DEFAULT_HOOK_TIMEOUT),
+            )?,
+            path,
+            path_exists,
+        })
+    }
+}
+
+fn resolve<T>(
+    env_var: &str,
+    parse: impl Fn(&str) -> Result<T>,
+    from_file: Option<T>,
+    default: T,
+) -> Result<Setting<T>> {
+    if let Ok(value) = env::var(env_var) {
+        return Ok(Setting {
+            value: parse(&value)
+                .with_context(|| format!("Invalid value in environment variable {env_var}"))?,
+            source: Source::Env,
+        });
+    }
+
+    Ok(match from_file {
+        Some(value) => Setting {
+            value,
+            source: Source::File,
+        },
+        None => Setting {
+            value: default,
+            source: Source::Default,
+        },
+    })
+}
+
+/// Parse a comma-separated list from an environment variable
+fn parse_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+impl<T> Setting<T> {
+    /// Override the value with the one provided on the command line, if any
+    pub fn set_from_cli(&mut self, value: Option<T>) {
+        if let Some(value) = value {
+            self.value = value;
+            self.source = Source::Cli;
+        }
+    }
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::File => write!(f, "config file"),
+            Source::Env => write!(f, "environment"),
+            Source::Cli => write!(f, "command line"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::{parse_list, resolve, Source};
+
+    fn parse_number(value: &str) -> anyhow::Result<u64> {
+        Ok(value.parse()?)
+    }
+
+    #[test]
+    fn settings_precedence() {
+        // Each case uses its own variable as the tests share the process' environment
+        let setting = resolve("BJOBS_TEST_UNSET", parse_number, None, 1).unwrap();
+        assert!(matches!(setting.source, Source::Default) && setting.value == 1);
+
+        let setting = resolve("BJOBS_TEST_UNSET", parse_number, Some(2), 1).unwrap();
+        assert!(matches!(setting.source, Source::File) && setting.value == 2);
+
+        env::set_var("BJOBS_TEST_PRECEDENCE", "3");
+        let mut setting = resolve("BJOBS_TEST_PRECEDENCE", parse_number, Some(2), 1).unwrap();
+        assert!(matches!(setting.source, Source::Env) && setting.value == 3);
+
+        setting.set_from_cli(None);
+        assert!(matches!(setting.source, Source::Env) && setting.value == 3);
+
+        setting.set_from_cli(Some(4));
+        assert!(matches!(setting.source, Source::Cli) && setting.value == 4);
+    }
+
+    #[test]
+    fn invalid_env_value() {
+        env::set_var("BJOBS_TEST_INVALID", "not a number");
+        assert!(resolve("BJOBS_TEST_INVALID", parse_number, Some(2), 1).is_err());
+    }
+
+    #[test]
+    fn parse_lists() {
+        assert_eq!(
+            parse_list(" alice, @staff ,,1000"),
+            ["alice", "@staff", "1000"]
+        );
+        assert!(parse_list("").is_empty());
+    }
+}
@@ -0,0 +1,163 @@
+use nix::unistd::{getuid, Gid, Group, Uid, User};
+
+use crate::{
+    config::Config,
+    ipc::{Access, Peer},
+    warn,
+};
+
+/// Permissions of the daemon's socket, which must be accessible to the users in the access lists
+pub struct SocketPermissions {
+    pub mode: u32,
+    /// Group the socket is given to, when the access lists only contain this group
+    pub group: Option<u32>,
+}
+
+/// Get the permissions of the daemon's socket, only opening it to everyone when the access lists can't be matched by a single group
+///
+/// Connections are still checked with [`AccessPolicy::authorize`] in every case.
+pub fn socket_permissions(config: &Config) -> SocketPermissions {
+    let principals = config
+        .full_access
+        .value
+        .iter()
+        .chain(&config.read_only_access.value)
+        .filter_map(|entry| resolve_principal(entry))
+        .collect::<Vec<_>>();
+
+    let group = match principals.first() {
+        Some(Principal::Group(gid)) => Some(*gid),
+        _ => None,
+    };
+
+    let single_group = group.filter(|gid| {
+        principals
+            .iter()
+            .all(|principal| matches!(principal, Principal::Group(other) if other == gid))
+    });
+
+    match (principals.is_empty(), single_group) {
+        (true, _) => SocketPermissions {
+            mode: 0o600,
+            group: None,
+        },
+        (false, Some(gid)) => SocketPermissions {
+            mode: 0o660,
+            group: Some(gid),
+        },
+        (false, None) => SocketPermissions {
+            mode: 0o666,
+            group: None,
+        },
+    }
+}
+
+/// Users and groups allowed to connect to the daemon, besides the user running it
+pub struct AccessPolicy {
+    owner: u32,
+    full: Vec<Principal>,
+    read_only: Vec<Principal>,
+}
+
+enum Principal {
+    User(u32),
+    Group(u32),
+}
+
+impl AccessPolicy {
+    /// Resolve the users and groups from the configuration, ignoring the unknown ones
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            owner: getuid().as_raw(),
+            full: resolve_principals(&config.full_access.value),
+            read_only: resolve_principals(&config.read_only_access.value),
+        }
+    }
+
+    /// Get the access of the user on the other side of the socket
+    pub fn authorize(&self, peer: &Peer) -> Access {
+        let &Peer { pid, uid, gid } = peer;
+
+        // The superuser could act as the owner anyway
+        let access = if uid == self.owner
+            || uid == 0
+            || self
+                .full
+                .iter()
+                .any(|principal| principal.matches(uid, gid))
+        {
+            Access::Full
+        } else if self
+            .read_only
+            .iter()
+            .any(|principal| principal.matches(uid, gid))
+        {
+            Access::ReadOnly
+        } else {
+            Access::Denied
+        };
+
+        if access == Access::Denied {
+            warn!("Refused connection from UID {uid} (PID {pid})");
+        }
+
+        access
+    }
+}
+
+impl Principal {
+    fn matches(&self, uid: u32, gid: u32) -> bool {
+        match self {
+            Principal::User(allowed) => *allowed == uid,
+            Principal::Group(allowed) => *allowed == gid || is_group_member(*allowed, uid),
+        }
+    }
+}
+
+/// Parse users (by name or UID) and groups (`@` followed by a name or GID)
+fn resolve_principals(entries: &[String]) -> Vec<Principal> {
+    entries
+        .iter()
+        .filter_map(|entry| {
+            let principal = resolve_principal(entry);
+
+            if principal.is_none() {
+                warn!("Ignoring unknown user or group '{entry}' in access lists");
+            }
+
+            principal
+        })
+        .collect()
+}
+
+fn resolve_principal(entry: &str) -> Option<Principal> {
+    match entry.strip_prefix('@') {
+        Some(group) => match group.parse() {
+            Ok(gid) => Some(Principal::Group(gid)),
+            Err(_) => Group::from_name(group)
+                .ok()
+                .flatten()
+                .map(|group| Principal::Group(group.gid.as_raw())),
+        },
+        None => match entry.parse() {
+            Ok(uid) => Some(Principal::User(uid)),
+            Err(_) => User::from_name(entry)
+                .ok()
+                .flatten()
+                .map(|user| Principal::User(user.uid.as_raw())),
+        },
+    }
+}
+
+/// Check the supplementary groups, as the kernel only provides the client's primary group
+fn is_group_member(gid: u32, uid: u32) -> bool {
+    let Ok(Some(user)) = User::from_uid(Uid::from_raw(uid)) else {
+        return false;
+    };
+
+    let Ok(Some(group)) = Group::from_gid(Gid::from_raw(gid)) else {
+        return false;
+    };
+
+    group.mem.contains(&user.name)
+}
@@ -0,0 +1,416 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{Read, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::{Context, Result};
+use hmac::{Hmac, Mac};
+use nix::unistd::{Uid, User};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::Sha256;
+use time::OffsetDateTime;
+
+use crate::{datetime::get_now, error, ipc::Peer, secrets::REDACTED, warn};
+
+pub fn audit_file(data_dir: &Path) -> PathBuf {
+    data_dir.join("audit.log")
+}
+
+fn audit_key_file(data_dir: &Path) -> PathBuf {
+    data_dir.join("audit.key")
+}
+
+/// Read the secret key the entries' hashes are computed with
+pub fn read_audit_key(data_dir: &Path) -> Result<Vec<u8>> {
+    fs::read(audit_key_file(data_dir)).context("Failed to read the audit log's key")
+}
+
+/// Get the key, generating it on first use so the hashes can't be computed without access to it
+fn load_or_create_audit_key(data_dir: &Path) -> Result<Vec<u8>> {
+    let path = audit_key_file(data_dir);
+
+    if path.exists() {
+        return read_audit_key(data_dir);
+    }
+
+    let mut key = vec![0; 32];
+
+    fs::File::open("/dev/urandom")
+        .and_then(|mut file| file.read_exact(&mut key))
+        .context("Failed to generate the audit log's key")?;
+
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&path)
+        .and_then(|mut file| file.write_all(&key))
+        .context("Failed to write the audit log's key")?;
+
+    Ok(key)
+}
+
+/// Who made a call
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Caller {
+    /// Interface the call was received on: `socket` or `http`
+    pub via: String,
+    pub pid: Option<i32>,
+    pub uid: Option<u32>,
+    pub user: Option<String>,
+}
+
+impl Caller {
+    pub fn socket(peer: Peer) -> Self {
+        Self::new("socket", Some(peer))
+    }
+
+    /// Credentials are only known for the HTTP API's Unix socket
+    pub fn http(peer: Option<Peer>) -> Self {
+        Self::new("http", peer)
+    }
+
+    fn new(via: &str, peer: Option<Peer>) -> Self {
+        Self {
+            via: via.to_owned(),
+            pid: peer.map(|peer| peer.pid),
+            uid: peer.map(|peer| peer.uid),
+            user: peer
+                .and_then(|peer| User::from_uid(Uid::from_raw(peer.uid)).ok().flatten())
+                .map(|user| user.name),
+        }
+    }
+}
+
+/// Name and arguments of a call
+pub struct Call {
+    pub name: String,
+    pub args: Value,
+}
+
+impl Call {
+    /// Describe a service's request, which is serialized as the function's name holding its arguments
+    pub fn describe(req: &impl Serialize) -> Self {
+        let value = serde_json::to_value(req).unwrap_or(Value::Null);
+
+        match value {
+            Value::Object(object) if object.len() == 1 => {
+                let (name, mut args) = object.into_iter().next().unwrap();
+
+                // Keep the names of the secrets but never their values
+                if let Some(Value::Object(secrets)) = args.get_mut("secrets") {
+                    for value in secrets.values_mut() {
+                        *value = Value::String(REDACTED.to_owned());
+                    }
+                }
+
+                Self { name, args }
+            }
+            Value::String(name) => Self {
+                name,
+                args: Value::Null,
+            },
+            args => Self {
+                name: "unknown".to_owned(),
+                args,
+            },
+        }
+    }
+}
+
+/// Get the error returned by a function of a service, if it returns a `Result`
+pub fn response_error(res: &impl Serialize) -> Option<String> {
+    let value = serde_json::to_value(res).ok()?;
+
+    value
+        .as_object()?
+        .values()
+        .next()?
+        .get("Err")
+        .map(|err| err.as_str().map_or_else(|| err.to_string(), str::to_owned))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AuditRecord {
+    #[serde(with = "time::serde::rfc3339")]
+    pub at: OffsetDateTime,
+    pub caller: Caller,
+    pub call: String,
+    pub args: Value,
+    /// Error returned by the call, if it failed
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AuditEntry {
+    #[serde(flatten)]
+    pub record: AuditRecord,
+    /// Hash of the previous entry, so removing or modifying entries breaks the chain
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+impl AuditEntry {
+    /// Check if the call was about a task
+    pub fn mentions_task(&self, task_name: &str) -> bool {
+        let args = &self.record.args;
+
+        args.get("task_name").and_then(Value::as_str) == Some(task_name)
+            || args.pointer("/task/name").and_then(Value::as_str) == Some(task_name)
+            || args
+                .get("task_names")
+                .and_then(Value::as_array)
+                .is_some_and(|names| names.iter().any(|name| name.as_str() == Some(task_name)))
+    }
+
+    fn compute_hash(key: &[u8], record: &AuditRecord, prev_hash: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any size");
+
+        mac.update(prev_hash.as_bytes());
+        mac.update(serde_json::to_string(record).unwrap().as_bytes());
+
+        mac.finalize()
+            .into_bytes()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
+    }
+}
+
+/// Hash preceding the first entry
+fn initial_hash() -> String {
+    "0".repeat(64)
+}
+
+/// Append-only log of the calls changing the daemon's state, as lines of JSON
+pub struct AuditLog {
+    path: PathBuf,
+    key: Vec<u8>,
+    /// Hash of the last entry, locked while writing to keep the entries chained in order
+    last_hash: Mutex<String>,
+}
+
+impl AuditLog {
+    /// Open the log, continuing the chain of a previous daemon process
+    pub fn open(data_dir: &Path) -> Result<Self> {
+        let path = audit_file(data_dir);
+        let key = load_or_create_audit_key(data_dir)?;
+
+        let AuditLogContent {
+            entries,
+            invalid_lines,
+        } = read_audit_log(&path)?;
+
+        if !invalid_lines.is_empty() {
+            warn!(
+                "Audit log has invalid entries at line(s) {}, continuing from the last valid one",
+                join_lines(&invalid_lines)
+            );
+        }
+
+        let last_hash = entries
+            .last()
+            .map_or_else(initial_hash, |entry| entry.hash.clone());
+
+        Ok(Self {
+            path,
+            key,
+            last_hash: Mutex::new(last_hash),
+        })
+    }
+
+    /// Hash of the last entry, to detect entries removed from the end of the log
+    pub fn last_hash(&self) -> String {
+        self.last_hash.lock().unwrap().clone()
+    }
+
+    pub fn record(&self, caller: Caller, call: Call, error: Option<String>) {
+        let record = AuditRecord {
+            at: get_now(),
+            caller,
+            call: call.name,
+            args: call.args,
+            error,
+        };
+
+        let mut last_hash = self.last_hash.lock().unwrap();
+
+        let hash = AuditEntry::compute_hash(&self.key, &record, &last_hash);
+
+        let entry = AuditEntry {
+            record,
+            prev_hash: last_hash.clone(),
+            hash: hash.clone(),
+        };
+
+        let mut line = serde_json::to_string(&entry).unwrap();
+        line.push('\n');
+
+        let written = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .mode(0o600)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(line.as_bytes()));
+
+        match written {
+            Ok(()) => *last_hash = hash,
+            Err(err) => error!("Failed to write to the audit log: {err}"),
+        }
+    }
+}
+
+pub struct AuditLogContent {
+    pub entries: Vec<AuditEntry>,
+    /// Number of the lines that couldn't be parsed, e.g. if the daemon crashed while writing them
+    pub invalid_lines: Vec<usize>,
+}
+
+/// Read the entries of the audit log
+pub fn read_audit_log(path: &Path) -> Result<AuditLogContent> {
+    let mut content = AuditLogContent {
+        entries: vec![],
+        invalid_lines: vec![],
+    };
+
+    if !path.exists() {
+        return Ok(content);
+    }
+
+    let lines = fs::read_to_string(path).context("Failed to read the audit log")?;
+
+    for (i, line) in lines.lines().enumerate() {
+        if line.is_empty() {
+            continue;
+        }
+
+        match serde_json::from_str(line) {
+            Ok(entry) => content.entries.push(entry),
+            Err(_) => content.invalid_lines.push(i + 1),
+        }
+    }
+
+    Ok(content)
+}
+
+fn join_lines(lines: &[usize]) -> String {
+    lines
+        .iter()
+        .map(usize::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Check that the entries weren't modified, removed or reordered
+///
+/// The last hash held by the running daemon, if provided, must be in the log so removing entries from the end is detected.
+///
+/// Returns the index of the first invalid entry, or the number of entries if some are missing at the end.
+pub fn verify_audit_log(
+    entries: &[AuditEntry],
+    key: &[u8],
+    last_hash: Option<&str>,
+) -> Option<usize> {
+    let mut prev_hash = initial_hash();
+
+    for (i, entry) in entries.iter().enumerate() {
+        if entry.prev_hash != prev_hash
+            || entry.hash != AuditEntry::compute_hash(key, &entry.record, &entry.prev_hash)
+        {
+            return Some(i);
+        }
+
+        prev_hash = entry.hash.clone();
+    }
+
+    match last_hash {
+        // Entries may have been added since the daemon provided its last hash
+        Some(last_hash) if last_hash != initial_hash() => {
+            if entries.iter().any(|entry| entry.hash == last_hash) {
+                None
+            } else {
+                Some(entries.len())
+            }
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{initial_hash, verify_audit_log, AuditEntry, AuditRecord, Caller};
+    use crate::datetime::get_now;
+
+    const KEY: &[u8] = b"key";
+
+    fn chain(key: &[u8], calls: &[&str]) -> Vec<AuditEntry> {
+        let mut prev_hash = initial_hash();
+
+        calls
+            .iter()
+            .map(|call| {
+                let record = AuditRecord {
+                    at: get_now(),
+                    caller: Caller::http(None),
+                    call: (*call).to_owned(),
+                    args: json!({ "task_name": "task" }),
+                    error: None,
+                };
+
+                let hash = AuditEntry::compute_hash(key, &record, &prev_hash);
+                AuditEntry {
+                    record,
+                    prev_hash: std::mem::replace(&mut prev_hash, hash.clone()),
+                    hash,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn verify_intact_log() {
+        let entries = chain(KEY, &["run", "kill", "remove"]);
+
+        assert_eq!(verify_audit_log(&entries, KEY, None), None);
+        assert_eq!(
+            verify_audit_log(&entries, KEY, Some(&entries[2].hash)),
+            None
+        );
+        assert_eq!(
+            verify_audit_log(&entries, KEY, Some(&entries[1].hash)),
+            None
+        );
+        assert_eq!(verify_audit_log(&[], KEY, Some(&initial_hash())), None);
+    }
+
+    #[test]
+    fn verify_edited_log() {
+        let mut entries = chain(KEY, &["run", "kill", "remove"]);
+        entries[1].record.call = "restart".to_owned();
+        assert_eq!(verify_audit_log(&entries, KEY, None), Some(1));
+
+        // Recomputing the hashes requires the key
+        let forged = chain(b"other key", &["run", "kill", "remove"]);
+        assert_eq!(verify_audit_log(&forged, KEY, None), Some(0));
+
+        let mut entries = chain(KEY, &["run", "kill", "remove"]);
+        entries.remove(1);
+        assert_eq!(verify_audit_log(&entries, KEY, None), Some(1));
+    }
+
+    #[test]
+    fn verify_truncated_log() {
+        let mut entries = chain(KEY, &["run", "kill", "remove"]);
+        let last_hash = entries.pop().unwrap().hash;
+
+        assert_eq!(verify_audit_log(&entries, KEY, None), None);
+        assert_eq!(verify_audit_log(&entries, KEY, Some(&last_hash)), Some(2));
+        assert_eq!(verify_audit_log(&[], KEY, Some(&last_hash)), Some(0));
+    }
+}
@@ -0,0 +1,27 @@
+use std::{collections::BTreeSet, io, process::Command, sync::Mutex};
+
+use command_group::{CommandGroup, GroupChild};
+
+/// PID of the processes collected by the thread that spawned them, e.g. tasks and hooks
+static SPAWNED_CHILDREN: Mutex<BTreeSet<u32>> = Mutex::new(BTreeSet::new());
+
+/// Spawn a command as a process group leader, which the reaper leaves alone until [`forget_child`] is called
+pub fn spawn_child(cmd: &mut Command) -> io::Result<GroupChild> {
+    // Held until the PID is registered, so the process can't be collected by the reaper if it exits right away
+    let mut spawned = SPAWNED_CHILDREN.lock().unwrap();
+
+    let child = cmd.group_spawn()?;
+    spawned.insert(child.id());
+
+    Ok(child)
+}
+
+/// Let the reaper collect a process once the thread that spawned it is done with it
+pub fn forget_child(pid: u32) {
+    SPAWNED_CHILDREN.lock().unwrap().remove(&pid);
+}
+
+/// Check if a process is collected by the thread that spawned it, so nothing else must collect it
+pub fn is_spawned_child(pid: u32) -> bool {
+    SPAWNED_CHILDREN.lock().unwrap().contains(&pid)
+}
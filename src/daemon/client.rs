@@ -1,26 +1,50 @@
-use std::{io::ErrorKind, os::unix::net::UnixStream, path::Path};
+use std::{
+    path::Path,
+    process::{Command, Stdio},
+};
 
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
 
-use crate::ipc::SocketClient;
+use crate::debug;
+
+use super::is_daemon_running;
 
 pub use super::service::daemon::Client as DaemonClient;
 
 impl DaemonClient {
     pub fn connect(socket_path: &Path) -> Result<Self> {
-        if !socket_path.exists() {
-            bail!("Daemon is not running.");
+        Self::new(socket_path)
+    }
+
+    /// Connect to the daemon, starting it first if it isn't running yet
+    pub fn connect_or_start(socket_path: &Path, data_dir: &Path) -> Result<Self> {
+        if is_daemon_running(socket_path)? {
+            return Self::connect(socket_path);
         }
 
-        match UnixStream::connect(socket_path) {
-            Ok(stream) => Ok(Self {
-                inner: SocketClient::new(stream),
-            }),
+        debug!("Daemon is not running, starting it...");
+
+        // The daemon is started from a separate process as daemonizing exits the parent process
+        let status = Command::new(
+            std::env::current_exe().context("Failed to get path to the current executable")?,
+        )
+        .arg("--custom-data-dir")
+        .arg(data_dir)
+        .arg("start")
+        .arg("--ignore-started")
+        .stdout(Stdio::null())
+        .status()
+        .context("Failed to run the daemon")?;
 
-            Err(err) => match err.kind() {
-                ErrorKind::ConnectionRefused => bail!("Daemon is not running."),
-                err => bail!("Failed to handle the socket file: {}", err),
-            },
+        if !status.success() {
+            bail!("Failed to start the daemon");
         }
+
+        let client = Self::connect(socket_path)?;
+
+        let pid = client.hello()?;
+        debug!("Daemon started with PID {pid}.");
+
+        Ok(client)
     }
 }
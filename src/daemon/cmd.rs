@@ -1,7 +1,81 @@
-use clap::Args;
+use std::path::PathBuf;
+
+use clap::{Args, Subcommand, ValueEnum};
 
 #[derive(Args)]
 pub struct DaemonStartArgs {
     #[clap(long, help = "Do nothing if the daemon is already started")]
     pub ignore_started: bool,
+
+    #[clap(
+        long,
+        value_enum,
+        default_value = "adopt",
+        help = "What to do with the tasks left running by a previous daemon process that crashed"
+    )]
+    pub orphans: OrphanPolicy,
+
+    #[clap(
+        long,
+        help = "Don't detach from the terminal and log to STDERR, e.g. in a container or as a systemd service"
+    )]
+    pub foreground: bool,
+
+    /// Used by the daemon to take over the state of a previous daemon process
+    #[clap(long, hide = true)]
+    pub resume_from: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum OrphanPolicy {
+    /// Monitor them until they exit
+    Adopt,
+    /// List them as orphaned so they can be killed
+    Report,
+}
+
+#[derive(Args, Clone)]
+pub struct DaemonStopArgs {
+    #[clap(
+        long,
+        help = "Let running tasks complete before exiting, new tasks are refused in the meantime"
+    )]
+    pub wait: bool,
+
+    #[clap(
+        long,
+        requires = "wait",
+        help = "Maximum number of seconds to wait for, after which remaining tasks are killed"
+    )]
+    pub timeout: Option<u64>,
+
+    #[clap(
+        long,
+        conflicts_with = "wait",
+        help = "Kill all tasks, including the ones flagged to keep running"
+    )]
+    pub force: bool,
+}
+
+#[derive(Subcommand)]
+pub enum DaemonAction {
+    #[clap(
+        about = "Restart the daemon without stopping running tasks, e.g. to use an upgraded binary"
+    )]
+    Restart,
+
+    #[clap(about = "Make the daemon read its configuration again (same as sending SIGHUP)")]
+    Reload,
+
+    #[clap(about = "Generate systemd user units to start the daemon on demand")]
+    InstallUnit(InstallUnitArgs),
+}
+
+#[derive(Args)]
+pub struct InstallUnitArgs {
+    #[clap(long, help = "Print the units instead of writing them")]
+    pub print: bool,
+
+    #[clap(long, help = "Overwrite existing units")]
+    pub force: bool,
 }
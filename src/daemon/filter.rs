@@ -0,0 +1,115 @@
+use clap::ValueEnum;
+use glob::Pattern;
+use serde::{Deserialize, Serialize};
+
+use crate::task::Task;
+
+use super::TaskStatus;
+
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct TaskFilter {
+    /// Glob patterns matched against the tasks' name
+    pub names: Vec<String>,
+    pub tags: Vec<String>,
+    pub statuses: Vec<StatusKind>,
+}
+
+impl TaskFilter {
+    pub fn matches(&self, task: &Task, status: &TaskStatus) -> bool {
+        (self.names.is_empty() || self.names.iter().any(|name| glob_matches(name, &task.name)))
+            && (self.tags.is_empty() || self.tags.iter().any(|tag| task.tags.contains(tag)))
+            && (self.statuses.is_empty() || self.statuses.contains(&StatusKind::of(status)))
+    }
+}
+
+fn glob_matches(pattern: &str, name: &str) -> bool {
+    match Pattern::new(pattern) {
+        Ok(pattern) => pattern.matches(name),
+        // Invalid patterns are treated as plain names
+        Err(_) => pattern == name,
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+pub enum StatusKind {
+    Pending,
+    Running,
+    Succeeded,
+    Failed,
+    /// Processes left by a daemon that crashed, which are not monitored
+    Orphaned,
+    /// Adopted processes that exited with an unknown status
+    Unknown,
+}
+
+impl StatusKind {
+    pub fn of(status: &TaskStatus) -> Self {
+        match status {
+            TaskStatus::NotStartedYet => Self::Pending,
+            TaskStatus::Running { child: _ } => Self::Running,
+            TaskStatus::Success => Self::Succeeded,
+            TaskStatus::Failed { .. } | TaskStatus::RunnerFailed { .. } => Self::Failed,
+            TaskStatus::Orphaned => Self::Orphaned,
+            TaskStatus::Exited => Self::Unknown,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{StatusKind, TaskFilter};
+    use crate::{daemon::TaskStatus, task::Task};
+
+    fn task(name: &str, tags: &[&str]) -> Task {
+        serde_json::from_value(json!({ "name": name, "cmd": "true", "tags": tags })).unwrap()
+    }
+
+    fn names(names: &[&str]) -> TaskFilter {
+        TaskFilter {
+            names: names.iter().map(|name| (*name).to_owned()).collect(),
+            ..TaskFilter::default()
+        }
+    }
+
+    #[test]
+    fn match_name_globs() {
+        let status = TaskStatus::Success;
+
+        assert!(TaskFilter::default().matches(&task("build", &[]), &status));
+        assert!(names(&["build"]).matches(&task("build", &[]), &status));
+        assert!(!names(&["build"]).matches(&task("build-docs", &[]), &status));
+        assert!(names(&["build-*"]).matches(&task("build-docs", &[]), &status));
+        assert!(names(&["test", "build-?"]).matches(&task("build-1", &[]), &status));
+        assert!(names(&["[ab]*"]).matches(&task("backup", &[]), &status));
+        assert!(!names(&["[ab]*"]).matches(&task("clean", &[]), &status));
+    }
+
+    #[test]
+    fn match_invalid_globs_as_names() {
+        let status = TaskStatus::Success;
+
+        assert!(names(&["[build"]).matches(&task("[build", &[]), &status));
+        assert!(!names(&["[build"]).matches(&task("build", &[]), &status));
+    }
+
+    #[test]
+    fn match_all_criteria() {
+        let filter = TaskFilter {
+            names: vec!["web-*".to_owned()],
+            tags: vec!["prod".to_owned()],
+            statuses: vec![StatusKind::Failed, StatusKind::Unknown],
+        };
+        let failed = TaskStatus::Failed {
+            code: Some(1),
+            signal: None,
+        };
+
+        assert!(filter.matches(&task("web-api", &["prod"]), &failed));
+        assert!(filter.matches(&task("web-api", &["prod"]), &TaskStatus::Exited));
+        assert!(!filter.matches(&task("web-api", &["prod"]), &TaskStatus::Success));
+        assert!(!filter.matches(&task("web-api", &["dev"]), &failed));
+        assert!(!filter.matches(&task("db", &["prod"]), &failed));
+    }
+}
@@ -0,0 +1,167 @@
+use std::{
+    collections::BTreeMap,
+    convert::Infallible,
+    fs,
+    os::{fd::RawFd, unix::process::CommandExt},
+    path::{Path, PathBuf},
+    process::Command,
+    sync::atomic::Ordering,
+};
+
+use anyhow::{Context, Result};
+use nix::{
+    fcntl::{fcntl, FcntlArg, FdFlag},
+    unistd::getpid,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    logging::{LOG_TO_STDERR, PRINT_DEBUG_MESSAGES},
+    private_file::write_private_file,
+    task::Task,
+};
+
+use super::{
+    service::State,
+    task::{TaskState, TaskWrapper},
+};
+
+/// State passed from a daemon process to the one replacing it
+pub struct Handoff {
+    pub tasks: Vec<TaskWrapper>,
+}
+
+#[derive(Deserialize)]
+struct HandoffContent {
+    tasks: Vec<HandoffTask>,
+}
+
+#[derive(Deserialize)]
+struct HandoffTask {
+    task: Task,
+    state: TaskState,
+    #[serde(default)]
+    secrets: BTreeMap<String, String>,
+}
+
+#[derive(Serialize)]
+struct HandoffRef<'a> {
+    tasks: Vec<HandoffTaskRef<'a>>,
+}
+
+#[derive(Serialize)]
+struct HandoffTaskRef<'a> {
+    task: &'a Task,
+    state: &'a TaskState,
+    /// Not part of the task's state as they must never be sent to clients
+    secrets: &'a BTreeMap<String, String>,
+}
+
+impl Handoff {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path).context("Failed to read the handoff file")?;
+
+        fs::remove_file(path).context("Failed to remove the handoff file")?;
+
+        let content = serde_json::from_str::<HandoffContent>(&content)
+            .context("Failed to parse the handoff file")?;
+
+        let tasks = content
+            .tasks
+            .into_iter()
+            .map(
+                |HandoffTask {
+                     task,
+                     state,
+                     secrets,
+                 }| {
+                    let wrapper = TaskWrapper::new(task);
+                    *wrapper.state.lock().unwrap() = TaskState { secrets, ..state };
+                    wrapper
+                },
+            )
+            .collect();
+
+        Ok(Self { tasks })
+    }
+}
+
+pub fn handoff_file(data_dir: &Path) -> PathBuf {
+    data_dir.join("handoff.json")
+}
+
+/// Save the daemon's state and replace the current process with a new daemon which will take it over
+///
+/// As the process keeps the same PID, running tasks stay children of the daemon and their output
+/// is still written to their log file, so the new daemon can keep monitoring them.
+///
+/// A socket inherited from systemd is passed on the same way, so the new daemon keeps listening on it.
+pub fn hand_off(
+    state: &State,
+    data_dir: &Path,
+    inherited_socket: Option<RawFd>,
+) -> Result<Infallible> {
+    let guards = state
+        .tasks
+        .values()
+        .map(|wrapper| (&wrapper.task, wrapper.state.lock().unwrap()))
+        .collect::<Vec<_>>();
+
+    let handoff = HandoffRef {
+        tasks: guards
+            .iter()
+            .map(|(task, state)| HandoffTaskRef {
+                task,
+                state,
+                secrets: &state.secrets,
+            })
+            .collect(),
+    };
+
+    let handoff_file = handoff_file(data_dir);
+
+    let content = serde_json::to_string(&handoff).context("Failed to serialize the handoff")?;
+
+    // The file holds the tasks' secrets
+    write_private_file(&handoff_file, content.as_bytes())
+        .context("Failed to write the handoff file")?;
+
+    let exe = std::env::current_exe().context("Failed to get the daemon's binary path")?;
+
+    // When the binary was upgraded, the link points to the old (deleted) file
+    let exe = match exe.to_str().and_then(|exe| exe.strip_suffix(" (deleted)")) {
+        Some(exe) => PathBuf::from(exe),
+        None => exe,
+    };
+
+    let mut cmd = Command::new(exe);
+
+    if PRINT_DEBUG_MESSAGES.load(Ordering::SeqCst) {
+        cmd.arg("--verbose");
+    }
+
+    if let Some(fd) = inherited_socket {
+        fcntl(fd, FcntlArg::F_SETFD(FdFlag::empty()))
+            .context("Failed to keep the inherited socket open")?;
+
+        cmd.env("LISTEN_FDS", "1")
+            .env("LISTEN_PID", getpid().to_string());
+    }
+
+    cmd.arg("--custom-data-dir")
+        .arg(data_dir)
+        .arg("start")
+        .arg("--resume-from")
+        .arg(&handoff_file);
+
+    if LOG_TO_STDERR.load(Ordering::SeqCst) {
+        cmd.arg("--foreground");
+    }
+
+    // Only returns if the new daemon could not be executed
+    let err = cmd.exec();
+
+    let _ = fs::remove_file(&handoff_file);
+
+    Err(err).context("Failed to execute the new daemon")
+}
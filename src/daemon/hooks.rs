@@ -0,0 +1,323 @@
+use std::{
+    collections::BTreeMap,
+    fs::{File, OpenOptions, Permissions},
+    io::Write,
+    os::unix::{
+        fs::{OpenOptionsExt, PermissionsExt},
+        process::ExitStatusExt,
+    },
+    path::{Path, PathBuf},
+    process::Command,
+    sync::RwLock,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use command_group::GroupChild;
+
+use crate::{
+    config::Config,
+    datetime::{format_timestamp, get_now},
+    sleep::sleep_ms,
+    task::Task,
+    warn,
+};
+
+use super::{
+    children::{forget_child, spawn_child},
+    runner::task_log_file,
+    task::{TaskState, TaskStatus},
+};
+
+/// Moment of a task's life at which hooks are run
+#[derive(Clone, Copy)]
+pub enum HookEvent {
+    Start,
+    Success,
+    Failure,
+}
+
+impl HookEvent {
+    /// Get the event matching the status of a task that just completed
+    pub fn for_status(status: &TaskStatus) -> Option<Self> {
+        match status {
+            TaskStatus::Success => Some(Self::Success),
+            TaskStatus::Failed { .. } | TaskStatus::RunnerFailed { .. } => Some(Self::Failure),
+            TaskStatus::NotStartedYet
+            | TaskStatus::Running { child: _ }
+            | TaskStatus::Orphaned
+            | TaskStatus::Exited => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Start => "start",
+            Self::Success => "success",
+            Self::Failure => "failure",
+        }
+    }
+}
+
+/// Information about the task, provided to the hooks as environment variables
+pub struct HookContext {
+    pub pid: Option<u32>,
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+    pub duration_ms: Option<u64>,
+    pub restarts: usize,
+    pub log_file: Option<PathBuf>,
+    /// Reason why the task could not be run
+    pub error: Option<String>,
+}
+
+impl HookContext {
+    pub fn new(state: &TaskState) -> Self {
+        let (exit_code, signal, error) = match &state.status {
+            TaskStatus::Success => (Some(0), None, None),
+            TaskStatus::Failed { code, signal } => (*code, *signal, None),
+            TaskStatus::RunnerFailed { message } => (None, None, Some(message.clone())),
+            _ => (None, None, None),
+        };
+
+        Self {
+            pid: state.pid,
+            exit_code,
+            signal,
+            duration_ms: state.duration_ms(),
+            restarts: state.restarts,
+            log_file: state.log_file.clone(),
+            error,
+        }
+    }
+
+    fn vars(&self, event: HookEvent, task_name: &str) -> Vec<(&'static str, String)> {
+        let mut vars = vec![
+            ("BJOBS_HOOK", event.name().to_owned()),
+            ("BJOBS_TASK_NAME", task_name.to_owned()),
+            ("BJOBS_RESTARTS", self.restarts.to_string()),
+        ];
+
+        let optional = [
+            ("BJOBS_TASK_PID", self.pid.map(|pid| pid.to_string())),
+            (
+                "BJOBS_EXIT_CODE",
+                self.exit_code.map(|code| code.to_string()),
+            ),
+            ("BJOBS_SIGNAL", self.signal.map(|signal| signal.to_string())),
+            (
+                "BJOBS_DURATION_MS",
+                self.duration_ms.map(|ms| ms.to_string()),
+            ),
+            (
+                "BJOBS_LOG_FILE",
+                self.log_file
+                    .as_ref()
+                    .map(|path| path.display().to_string()),
+            ),
+            ("BJOBS_ERROR", self.error.clone()),
+        ];
+
+        vars.extend(
+            optional
+                .into_iter()
+                .filter_map(|(name, value)| Some((name, value?))),
+        );
+
+        vars
+    }
+}
+
+/// Hook to run, either defined by the task or globally in the configuration
+struct Hook {
+    scope: &'static str,
+    cmd: String,
+    shell: String,
+    start_dir: Option<PathBuf>,
+    env: BTreeMap<String, String>,
+    timeout: Option<Duration>,
+}
+
+pub fn hook_log_file(logs_dir: &Path, task_name: &str) -> PathBuf {
+    task_log_file(logs_dir, task_name).with_extension("hooks.log")
+}
+
+/// Run the task's hook for an event and then the global one, in the background
+pub fn run_hooks(
+    event: HookEvent,
+    task: &Task,
+    context: HookContext,
+    logs_dir: &Path,
+    config: &RwLock<Config>,
+) {
+    let (global_cmd, default_shell, timeout) = {
+        let config = config.read().unwrap();
+
+        let global_cmd = match event {
+            HookEvent::Start => &config.on_start,
+            HookEvent::Success => &config.on_success,
+            HookEvent::Failure => &config.on_failure,
+        };
+
+        (
+            global_cmd.value.clone(),
+            config.default_shell.value.clone(),
+            config.hook_timeout.value.map(Duration::from_secs),
+        )
+    };
+
+    let task_cmd = match event {
+        HookEvent::Start => &task.on_start,
+        HookEvent::Success => &task.on_success,
+        HookEvent::Failure => &task.on_failure,
+    };
+
+    let mut hooks = vec![];
+
+    if let Some(cmd) = task_cmd {
+        hooks.push(Hook {
+            scope: "task",
+            cmd: cmd.clone(),
+            shell: task.shell.clone().unwrap_or_else(|| default_shell.clone()),
+            start_dir: task.start_dir.clone(),
+            env: task.env.clone(),
+            // Tasks can override the timeout, with 0 disabling it
+            timeout: match task.hook_timeout {
+                Some(secs) => Some(secs).filter(|secs| *secs > 0).map(Duration::from_secs),
+                None => timeout,
+            },
+        });
+    }
+
+    if let Some(cmd) = global_cmd {
+        hooks.push(Hook {
+            scope: "global",
+            cmd,
+            shell: default_shell,
+            start_dir: None,
+            env: BTreeMap::new(),
+            timeout,
+        });
+    }
+
+    if hooks.is_empty() {
+        return;
+    }
+
+    let vars = context.vars(event, &task.name);
+    let log_file = hook_log_file(logs_dir, &task.name);
+    let task_name = task.name.clone();
+
+    std::thread::spawn(move || {
+        for hook in hooks {
+            if let Err(err) = run_hook(&hook, event, &vars, &log_file) {
+                warn!(
+                    "Failed to run {} on_{} hook of task '{task_name}': {err:?}",
+                    hook.scope,
+                    event.name()
+                );
+            }
+        }
+    });
+}
+
+/// Run a hook until it exits or times out, appending its output to the task's hook log file
+fn run_hook(
+    hook: &Hook,
+    event: HookEvent,
+    vars: &[(&'static str, String)],
+    log_file: &Path,
+) -> Result<()> {
+    // Hooks can read the task's environment and raw log file, so their output may contain its secrets
+    let mut writer = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .mode(0o600)
+        .open(log_file)
+        .context("Failed to open the hook's log file")?;
+
+    // The file may have been created by an older version
+    writer
+        .set_permissions(Permissions::from_mode(0o600))
+        .context("Failed to restrict the hook's log file permissions")?;
+
+    log_line(
+        &mut writer,
+        &format!(
+            "Running {} on_{} hook: {}",
+            hook.scope,
+            event.name(),
+            hook.cmd
+        ),
+    )?;
+
+    let mut shell_cmd_parts = hook.shell.split(' ');
+
+    let mut cmd = Command::new(shell_cmd_parts.next().unwrap());
+
+    for part in shell_cmd_parts {
+        cmd.arg(part);
+    }
+
+    cmd.arg(&hook.cmd);
+
+    cmd.stdout(writer.try_clone().context("Failed to clone the writer")?);
+    cmd.stderr(writer.try_clone().context("Failed to clone the writer")?);
+
+    if let Some(start_dir) = &hook.start_dir {
+        cmd.current_dir(start_dir);
+    }
+
+    cmd.envs(&hook.env);
+    cmd.envs(vars.iter().map(|(name, value)| (*name, value)));
+
+    let mut handle = match spawn_child(&mut cmd) {
+        Ok(handle) => handle,
+        Err(err) => {
+            log_line(&mut writer, &format!("Failed to spawn the hook: {err}"))?;
+            return Err(err).context("Failed to spawn the hook");
+        }
+    };
+
+    let outcome = wait_for_hook(&mut handle, hook.timeout);
+
+    forget_child(handle.id());
+
+    log_line(&mut writer, &outcome?)
+}
+
+/// Wait for a hook to exit, killing it if it times out, and describe how it ended
+fn wait_for_hook(handle: &mut GroupChild, timeout: Option<Duration>) -> Result<String> {
+    let started_at = Instant::now();
+
+    loop {
+        if let Some(status) = handle
+            .try_wait()
+            .context("Failed to check the hook's status")?
+        {
+            return Ok(match (status.code(), status.signal()) {
+                (Some(code), _) => format!("Hook exited with code {code}"),
+                (None, Some(signal)) => format!("Hook was killed by signal {signal}"),
+                (None, None) => "Hook exited".to_owned(),
+            });
+        }
+
+        if let Some(timeout) = timeout.filter(|timeout| started_at.elapsed() >= *timeout) {
+            // Kill the hook's whole process group
+            handle.kill().context("Failed to kill the hook")?;
+            handle.wait().context("Failed to wait for the hook")?;
+
+            return Ok(format!(
+                "Hook timed out after {}s and was killed",
+                timeout.as_secs()
+            ));
+        }
+
+        sleep_ms(50);
+    }
+}
+
+fn log_line(writer: &mut File, message: &str) -> Result<()> {
+    writeln!(writer, "[{}] {message}", format_timestamp(get_now()))
+        .context("Failed to write to the hook's log file")
+}
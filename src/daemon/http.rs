@@ -0,0 +1,807 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, OpenOptions},
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    os::unix::{
+        fs::{FileTypeExt, OpenOptionsExt, PermissionsExt},
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    debug, info,
+    ipc::{peer_credentials, Peer},
+    sleep::sleep_ms,
+    task::Task,
+};
+
+use super::{
+    audit::{Call, Caller},
+    daemon::RequestContent,
+    kill, list, logs, remove, restart, run, task, StatusKind, TaskFilter, TaskWrapper,
+};
+
+type State = RwLock<super::State>;
+
+static MAX_HEAD_SIZE: u64 = 64 * 1024;
+static MAX_BODY_SIZE: usize = 16 * 1024 * 1024;
+static READ_TIMEOUT: Duration = Duration::from_secs(10);
+static STREAM_POLL_INTERVAL_MS: u64 = 250;
+static KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Serve the HTTP API on the address provided in the configuration
+///
+/// Requests on TCP must provide the token stored in the data directory, either in a `Authorization: Bearer` header
+/// or in a `token` query parameter as browsers can't set headers for event streams
+pub fn serve_http(listen: &str, data_dir: &Path, state: Arc<State>) -> Result<()> {
+    match listen.strip_prefix("unix:") {
+        Some(path) => {
+            let path = data_dir.join(path);
+
+            remove_stale_socket(&path)?;
+
+            let listener = UnixListener::bind(&path).with_context(|| {
+                format!("Failed to bind the HTTP API to socket '{}'", path.display())
+            })?;
+
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o600))
+                .context("Failed to set the HTTP API socket's permissions")?;
+
+            info!("HTTP API listening on socket {}", path.display());
+
+            accept(listener.incoming(), None, state);
+        }
+
+        None => {
+            let addrs = listen
+                .to_socket_addrs()
+                .with_context(|| format!("Invalid HTTP API address '{listen}'"))?
+                .collect::<Vec<_>>();
+
+            if addrs.is_empty() || addrs.iter().any(|addr| !addr.ip().is_loopback()) {
+                bail!("HTTP API can only listen on a loopback address, got '{listen}'");
+            }
+
+            let token = load_or_create_token(data_dir)?;
+
+            let listener = TcpListener::bind(addrs.as_slice())
+                .with_context(|| format!("Failed to bind the HTTP API to '{listen}'"))?;
+
+            info!(
+                "HTTP API listening on {} (token in {})",
+                listener.local_addr()?,
+                token_file(data_dir).display()
+            );
+
+            accept(listener.incoming(), Some(Arc::new(token)), state);
+        }
+    }
+
+    Ok(())
+}
+
+fn token_file(data_dir: &Path) -> PathBuf {
+    data_dir.join("http.token")
+}
+
+/// Get the token clients must provide, generating it on first use so it stays the same across restarts
+fn load_or_create_token(data_dir: &Path) -> Result<String> {
+    let path = token_file(data_dir);
+
+    if path.exists() {
+        let token = fs::read_to_string(&path).context("Failed to read the HTTP API token")?;
+
+        return Ok(token.trim().to_owned());
+    }
+
+    let mut bytes = [0; 32];
+
+    fs::File::open("/dev/urandom")
+        .and_then(|mut file| file.read_exact(&mut bytes))
+        .context("Failed to generate the HTTP API token")?;
+
+    let token = bytes
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<String>();
+
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&path)
+        .and_then(|mut file| file.write_all(token.as_bytes()))
+        .context("Failed to write the HTTP API token")?;
+
+    Ok(token)
+}
+
+/// Remove the socket left by a previous daemon process, refusing to remove anything else
+fn remove_stale_socket(path: &Path) -> Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {
+            fs::remove_file(path).context("Failed to remove the previous HTTP API socket")
+        }
+        Ok(_) => bail!(
+            "Refusing to replace '{}' which isn't a socket",
+            path.display()
+        ),
+        Err(_) => Ok(()),
+    }
+}
+
+trait HttpStream: Read + Write + Send + 'static {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+
+    /// Credentials of the client, only available on Unix sockets
+    fn peer(&self) -> Option<Peer>;
+}
+
+impl HttpStream for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn peer(&self) -> Option<Peer> {
+        None
+    }
+}
+
+impl HttpStream for UnixStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+
+    fn peer(&self) -> Option<Peer> {
+        peer_credentials(self).ok()
+    }
+}
+
+fn accept<S: HttpStream>(
+    incoming: impl Iterator<Item = io::Result<S>>,
+    token: Option<Arc<String>>,
+    state: Arc<State>,
+) {
+    for stream in incoming {
+        match stream {
+            Ok(stream) => {
+                let token = token.clone();
+                let state = Arc::clone(&state);
+
+                std::thread::spawn(move || {
+                    if let Err(err) =
+                        serve_client(stream, token.as_deref().map(String::as_str), state)
+                    {
+                        debug!("HTTP API client failed: {err}");
+                    }
+                });
+            }
+
+            Err(err) => debug!("Failed to accept HTTP API client: {err}"),
+        }
+    }
+}
+
+/// Body of a request creating a task
+#[derive(Deserialize)]
+struct NewTask {
+    #[serde(flatten)]
+    task: Task,
+    /// Values of the task's secret environment variables
+    #[serde(default)]
+    secrets: BTreeMap<String, String>,
+}
+
+struct Request {
+    method: String,
+    segments: Vec<String>,
+    query: Vec<(String, String)>,
+    headers: BTreeMap<String, String>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn query(&self, name: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn query_all(&self, name: &str) -> Vec<String> {
+        self.query
+            .iter()
+            .filter(|(key, _)| key == name)
+            .map(|(_, value)| value.clone())
+            .collect()
+    }
+
+    fn query_parsed<T: std::str::FromStr>(&self, name: &str) -> Result<Option<T>, Response> {
+        self.query(name)
+            .map(|value| {
+                value.parse().map_err(|_| {
+                    Response::error(400, format!("Invalid value for parameter '{name}'"))
+                })
+            })
+            .transpose()
+    }
+}
+
+struct Response {
+    status: u16,
+    body: Option<serde_json::Value>,
+}
+
+impl Response {
+    fn json(status: u16, body: impl Serialize) -> Self {
+        Self {
+            status,
+            body: Some(serde_json::to_value(body).expect("Failed to serialize response")),
+        }
+    }
+
+    fn empty() -> Self {
+        Self {
+            status: 204,
+            body: None,
+        }
+    }
+
+    fn error(status: u16, message: impl Into<String>) -> Self {
+        Self::json(status, json!({ "error": message.into() }))
+    }
+}
+
+enum Reply {
+    Response(Response),
+    /// Stream a task's output, starting from the provided line index
+    LogStream(Box<TaskWrapper>, usize),
+    /// Stream changes of the tasks' status
+    EventStream,
+}
+
+fn serve_client<S: HttpStream>(
+    mut stream: S,
+    token: Option<&str>,
+    state: Arc<State>,
+) -> Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+
+    let cors = cors_headers(&state);
+
+    let req = match read_request(&mut stream)? {
+        Ok(req) => req,
+        Err(res) => return write_response(&mut stream, &cors, res),
+    };
+
+    if let Some(token) = token {
+        let provided = req
+            .headers
+            .get("authorization")
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .or_else(|| req.query("token"));
+
+        if req.method != "OPTIONS"
+            && !provided.is_some_and(|provided| constant_time_eq(provided, token))
+        {
+            return write_response(
+                &mut stream,
+                &cors,
+                Response::error(401, "Invalid or missing token"),
+            );
+        }
+    }
+
+    match route(&req, stream.peer(), &state) {
+        Reply::Response(res) => write_response(&mut stream, &cors, res),
+        Reply::LogStream(wrapper, from) => stream_logs(&mut stream, &cors, *wrapper, from),
+        Reply::EventStream => stream_events(&mut stream, &cors, &state),
+    }
+}
+
+/// Read a request, or get the error to answer with if it's invalid
+fn read_request<S: HttpStream>(stream: &mut S) -> Result<Result<Request, Response>> {
+    let mut reader = BufReader::new(stream.take(MAX_HEAD_SIZE));
+
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+
+    let mut parts = line.split_whitespace();
+
+    let (Some(method), Some(target), Some(_)) = (parts.next(), parts.next(), parts.next()) else {
+        return Ok(Err(Response::error(400, "Invalid request line")));
+    };
+
+    let method = method.to_owned();
+
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    let segments = path
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| percent_decode(segment, false))
+        .collect();
+
+    let query = query
+        .split('&')
+        .filter(|param| !param.is_empty())
+        .map(|param| {
+            let (key, value) = param.split_once('=').unwrap_or((param, ""));
+            (percent_decode(key, true), percent_decode(value, true))
+        })
+        .collect();
+
+    let mut headers = BTreeMap::new();
+
+    loop {
+        line.clear();
+
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(Err(Response::error(400, "Incomplete request head")));
+        }
+
+        let line = line.trim_end_matches(['\r', '\n']);
+
+        if line.is_empty() {
+            break;
+        }
+
+        let Some((name, value)) = line.split_once(':') else {
+            return Ok(Err(Response::error(400, "Invalid header")));
+        };
+
+        headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_owned());
+    }
+
+    let length = match headers.get("content-length") {
+        Some(length) => match length.parse::<usize>() {
+            Ok(length) if length <= MAX_BODY_SIZE => length,
+            Ok(_) => return Ok(Err(Response::error(413, "Request body is too large"))),
+            Err(_) => return Ok(Err(Response::error(400, "Invalid content length"))),
+        },
+        None => 0,
+    };
+
+    let mut body = vec![0; length];
+
+    // Only the head's size is limited
+    reader.get_mut().set_limit(length as u64);
+    reader.read_exact(&mut body)?;
+
+    Ok(Ok(Request {
+        method,
+        segments,
+        query,
+        headers,
+        body,
+    }))
+}
+
+fn route(req: &Request, peer: Option<Peer>, state: &Arc<State>) -> Reply {
+    let segments = req.segments.iter().map(String::as_str).collect::<Vec<_>>();
+
+    let res = match (req.method.as_str(), segments.as_slice()) {
+        ("OPTIONS", _) => Response::empty(),
+
+        ("GET", ["version"]) => Response::json(
+            200,
+            json!({ "version": env!("CARGO_PKG_VERSION"), "pid": std::process::id() }),
+        ),
+
+        ("GET", ["tasks"]) => {
+            let statuses = req
+                .query_all("status")
+                .iter()
+                .map(|status| StatusKind::from_str(status, true))
+                .collect::<Result<Vec<_>, _>>();
+
+            match statuses {
+                Ok(statuses) => Response::json(
+                    200,
+                    list(
+                        Arc::clone(state),
+                        TaskFilter {
+                            names: req.query_all("name"),
+                            tags: req.query_all("tag"),
+                            statuses,
+                        },
+                    ),
+                ),
+                Err(err) => Response::error(400, format!("Invalid status: {err}")),
+            }
+        }
+
+        ("POST", ["tasks"]) => match serde_json::from_slice::<NewTask>(&req.body) {
+            Ok(NewTask {
+                task: new_task,
+                secrets,
+            }) => {
+                let name = new_task.name.clone();
+
+                let req = RequestContent::run {
+                    task: new_task.clone(),
+                    secrets: Some(secrets.clone()),
+                };
+
+                // Refused if a task with this name already exists
+                match audited(state, peer, &req, || {
+                    run(Arc::clone(state), new_task, secrets)
+                }) {
+                    Ok(()) => match task(Arc::clone(state), name) {
+                        Ok(wrapper) => Response::json(201, wrapper.summary()),
+                        Err(err) => Response::error(409, err),
+                    },
+                    Err(err) => Response::error(409, err),
+                }
+            }
+            Err(err) => Response::error(400, format!("Invalid task: {err}")),
+        },
+
+        ("GET", ["events"]) => return Reply::EventStream,
+
+        (method, ["tasks", name, rest @ ..]) => {
+            let wrapper = match task(Arc::clone(state), name.to_string()) {
+                Ok(wrapper) => wrapper,
+                Err(err) => return Reply::Response(Response::error(404, err)),
+            };
+
+            let name = name.to_string();
+            let state = Arc::clone(state);
+
+            match (method, rest) {
+                ("GET", []) => match req.query_parsed("lines") {
+                    Ok(lines) => Response::json(200, wrapper.details(lines.unwrap_or(10))),
+                    Err(res) => res,
+                },
+
+                ("DELETE", []) => {
+                    let req = RequestContent::remove {
+                        task_name: name.clone(),
+                    };
+
+                    to_response(audited(&state, peer, &req, || {
+                        remove(Arc::clone(&state), name)
+                    }))
+                }
+
+                ("POST", ["kill"]) => {
+                    let req = RequestContent::kill {
+                        task_name: name.clone(),
+                    };
+
+                    to_response(audited(&state, peer, &req, || {
+                        kill(Arc::clone(&state), name)
+                    }))
+                }
+
+                ("POST", ["restart"]) => {
+                    let req = RequestContent::restart {
+                        task_name: name.clone(),
+                    };
+
+                    to_response(audited(&state, peer, &req, || {
+                        restart(Arc::clone(&state), name)
+                    }))
+                }
+
+                ("GET", ["logs"]) => match req.query_parsed("from") {
+                    Ok(from) => match logs(state, name, from.unwrap_or(0)) {
+                        Ok(lines) => Response::json(200, lines),
+                        Err(err) => Response::error(409, err),
+                    },
+                    Err(res) => res,
+                },
+
+                ("GET", ["logs", "stream"]) => {
+                    // Browsers provide the last received event's ID when they reconnect
+                    let from = match req.headers.get("last-event-id") {
+                        Some(id) => id.parse::<usize>().map(|id| Some(id + 1)).map_err(|_| {
+                            Response::error(400, "Invalid value for header 'Last-Event-ID'")
+                        }),
+                        None => req.query_parsed("from"),
+                    };
+
+                    match from {
+                        Ok(from) => return Reply::LogStream(Box::new(wrapper), from.unwrap_or(0)),
+                        Err(res) => res,
+                    }
+                }
+
+                _ => Response::error(404, "No such endpoint"),
+            }
+        }
+
+        _ => Response::error(404, "No such endpoint"),
+    };
+
+    Reply::Response(res)
+}
+
+/// Call a function changing the daemon's state, recording it in the audit log like requests received on its socket
+fn audited(
+    state: &Arc<State>,
+    peer: Option<Peer>,
+    req: &RequestContent,
+    call: impl FnOnce() -> Result<(), String>,
+) -> Result<(), String> {
+    let audit = Arc::clone(&state.read().unwrap().audit);
+
+    let result = call();
+
+    audit.record(
+        Caller::http(peer),
+        Call::describe(req),
+        result.as_ref().err().cloned(),
+    );
+
+    result
+}
+
+fn to_response(result: Result<(), String>) -> Response {
+    match result {
+        Ok(()) => Response::empty(),
+        Err(err) => Response::error(409, err),
+    }
+}
+
+fn write_response(stream: &mut impl Write, cors: &str, res: Response) -> Result<()> {
+    let body = res
+        .body
+        .map(|body| body.to_string().into_bytes())
+        .unwrap_or_default();
+
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\nConnection: close\r\n{}Content-Length: {}\r\n",
+        res.status,
+        reason(res.status),
+        cors,
+        body.len()
+    );
+
+    if !body.is_empty() {
+        head.push_str("Content-Type: application/json\r\n");
+    }
+
+    head.push_str("\r\n");
+
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(&body)?;
+    stream.flush()?;
+
+    Ok(())
+}
+
+/// Headers allowing the configured origin to call the API from a browser, if any
+fn cors_headers(state: &State) -> String {
+    let state = state.read().unwrap();
+    let config = state.config.read().unwrap();
+
+    match &config.http_allow_origin.value {
+        Some(origin) => format!(
+            "Access-Control-Allow-Origin: {origin}\r\nVary: Origin\r\nAccess-Control-Allow-Methods: GET, POST, DELETE, OPTIONS\r\nAccess-Control-Allow-Headers: Authorization, Content-Type, Last-Event-ID\r\n"
+        ),
+        None => String::new(),
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        _ => "Unknown",
+    }
+}
+
+fn start_event_stream(stream: &mut impl Write, cors: &str) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nConnection: close\r\n{cors}Content-Type: text/event-stream\r\nCache-Control: no-cache\r\n\r\n"
+    )?;
+
+    stream.flush()
+}
+
+fn write_event(
+    stream: &mut impl Write,
+    event: &str,
+    id: Option<usize>,
+    data: &str,
+) -> io::Result<()> {
+    let mut message = format!("event: {event}\n");
+
+    if let Some(id) = id {
+        message.push_str(&format!("id: {id}\n"));
+    }
+
+    for line in data.split(['\r', '\n']) {
+        message.push_str(&format!("data: {line}\n"));
+    }
+
+    message.push('\n');
+
+    stream.write_all(message.as_bytes())?;
+    stream.flush()
+}
+
+/// Send the task's output lines as `log` events identified by their index, then an `end` event once it completed
+fn stream_logs(
+    stream: &mut impl Write,
+    cors: &str,
+    wrapper: TaskWrapper,
+    from: usize,
+) -> Result<()> {
+    start_event_stream(stream, cors)?;
+
+    let mut next = from;
+    let mut last_write = Instant::now();
+
+    loop {
+        let (lines, completed) = {
+            let state = wrapper.state.lock().unwrap();
+
+            // Indexes include the lines that were discarded because of the retention limit
+            next = next.max(state.discarded_lines);
+
+            let lines = lines_from(&state.output, state.discarded_lines, next).to_vec();
+
+            (lines, state.status.is_completed())
+        };
+
+        for line in lines {
+            write_event(stream, "log", Some(next), &line)?;
+            next += 1;
+            last_write = Instant::now();
+        }
+
+        if completed {
+            write_event(stream, "end", None, "")?;
+            return Ok(());
+        }
+
+        keep_alive(stream, &mut last_write)?;
+
+        sleep_ms(STREAM_POLL_INTERVAL_MS);
+    }
+}
+
+/// Get the output lines starting at an index, which may be past the end of the output
+fn lines_from(output: &[String], discarded_lines: usize, from: usize) -> &[String] {
+    output
+        .get(from.saturating_sub(discarded_lines)..)
+        .unwrap_or_default()
+}
+
+/// Send a `task` event with the task's summary when a task is added or its status changes,
+/// and a `removed` event with its name when it's removed
+fn stream_events(stream: &mut impl Write, cors: &str, state: &Arc<State>) -> Result<()> {
+    start_event_stream(stream, cors)?;
+
+    let mut known = BTreeMap::<String, serde_json::Value>::new();
+    let mut last_write = Instant::now();
+
+    loop {
+        let summaries = list(Arc::clone(state), TaskFilter::default());
+
+        let names = summaries
+            .iter()
+            .map(|summary| summary.task.name.clone())
+            .collect::<Vec<_>>();
+
+        for summary in summaries {
+            // The duration changes continuously, so it's not considered as a change
+            let key = json!([
+                summary.status,
+                summary.pid,
+                summary.restarts,
+                summary.acknowledged
+            ]);
+
+            if known.get(&summary.task.name) != Some(&key) {
+                write_event(stream, "task", None, &serde_json::to_string(&summary)?)?;
+                known.insert(summary.task.name, key);
+                last_write = Instant::now();
+            }
+        }
+
+        let removed = known
+            .keys()
+            .filter(|name| !names.contains(name))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        for name in removed {
+            write_event(
+                stream,
+                "removed",
+                None,
+                &json!({ "name": name }).to_string(),
+            )?;
+            known.remove(&name);
+            last_write = Instant::now();
+        }
+
+        keep_alive(stream, &mut last_write)?;
+
+        sleep_ms(STREAM_POLL_INTERVAL_MS);
+    }
+}
+
+/// Send a comment when nothing was sent for a while, so proxies keep the connection open and closed ones are detected
+fn keep_alive(stream: &mut impl Write, last_write: &mut Instant) -> io::Result<()> {
+    if last_write.elapsed() >= KEEP_ALIVE_INTERVAL {
+        stream.write_all(b": keep-alive\n\n")?;
+        stream.flush()?;
+        *last_write = Instant::now();
+    }
+
+    Ok(())
+}
+
+fn percent_decode(input: &str, plus_as_space: bool) -> String {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+
+                match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                    Some(byte) => {
+                        decoded.push(byte);
+                        i += 3;
+                        continue;
+                    }
+                    None => decoded.push(b'%'),
+                }
+            }
+            b'+' if plus_as_space => decoded.push(b' '),
+            byte => decoded.push(byte),
+        }
+
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Compare the provided token without leaking how much of it matched through timing
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::lines_from;
+
+    #[test]
+    fn lines_from_out_of_range() {
+        let output = vec!["a".to_owned(), "b".to_owned()];
+
+        assert_eq!(lines_from(&output, 0, 1), ["b".to_owned()]);
+        assert_eq!(lines_from(&output, 3, 4), ["b".to_owned()]);
+        assert!(lines_from(&output, 0, 2).is_empty());
+        assert!(lines_from(&output, 0, 50).is_empty());
+        assert!(lines_from(&output, 3, 50).is_empty());
+    }
+}
@@ -1,16 +1,30 @@
+mod access;
+mod audit;
+mod children;
 mod client;
 mod cmd;
+mod filter;
+mod handoff;
+mod hooks;
+mod http;
+mod recovery;
 mod runner;
 mod service;
 mod start;
+mod systemd;
 mod task;
 
+pub use audit::{
+    audit_file, read_audit_key, read_audit_log, verify_audit_log, AuditEntry, AuditLogContent,
+};
 pub use client::*;
 pub use cmd::*;
-pub use service::daemon::{Client, RequestContent, ResponseContent};
+pub use filter::*;
+pub use hooks::hook_log_file;
 pub use service::*;
 pub use start::*;
-pub use task::{TaskState, TaskStatus, TaskWrapper};
+pub use systemd::*;
+pub use task::{TaskDetails, TaskState, TaskStatus, TaskSummary, TaskWrapper};
 
 use std::{io::ErrorKind, os::unix::net::UnixStream, path::Path};
 
@@ -0,0 +1,240 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{datetime::get_now, error, private_file::write_private_file, secrets::redact, warn};
+
+use super::{
+    runner::redact_log_file,
+    service::State,
+    task::{TaskStatus, TaskWrapper},
+    OrphanPolicy,
+};
+
+/// Tasks registry persisted by the daemon, to find the processes it left behind if it crashes
+#[derive(Serialize, Deserialize)]
+struct PersistedState {
+    tasks: Vec<TaskWrapper>,
+    /// Value of the tasks' secret environment variables, by task name, to redact the output of recovered tasks
+    #[serde(default)]
+    secrets: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+/// Path to the persisted state, which is only left when the daemon didn't exit cleanly
+pub fn state_file(data_dir: &Path) -> PathBuf {
+    data_dir.join("state.json")
+}
+
+/// Serialize the daemon's state, without the tasks' output as it can be found in their log file
+///
+/// The result holds the tasks' secrets, so it must only be readable by the owner.
+pub fn serialize_state(state: &State) -> Result<String> {
+    let persisted = PersistedState {
+        tasks: state
+            .tasks
+            .values()
+            .map(|wrapper| TaskWrapper {
+                task: wrapper.task.clone(),
+                state: Arc::new(Mutex::new(
+                    wrapper.state.lock().unwrap().clone_without_output(),
+                )),
+            })
+            .collect(),
+        secrets: state
+            .tasks
+            .values()
+            .filter_map(|wrapper| {
+                let secrets = wrapper.state.lock().unwrap().secrets.clone();
+                (!secrets.is_empty()).then(|| (wrapper.task.name.clone(), secrets))
+            })
+            .collect(),
+    };
+
+    serde_json::to_string(&persisted).context("Failed to serialize the daemon's state")
+}
+
+/// Writes the daemon's state to disk whenever it changes
+pub struct StatePersistence {
+    state_file: PathBuf,
+    /// Last content written, or `None` once the daemon is exiting so the state isn't written anymore
+    persisted: Mutex<Option<String>>,
+}
+
+impl StatePersistence {
+    pub fn new(data_dir: &Path) -> Self {
+        Self {
+            state_file: state_file(data_dir),
+            persisted: Mutex::new(Some(String::new())),
+        }
+    }
+
+    /// Write the daemon's state if it changed since it was last persisted
+    ///
+    /// Must not be called while holding the lock of a task's state.
+    pub fn persist(&self, state: &State) {
+        // Held while writing so a stale state can't replace a newer one
+        let mut persisted = self.persisted.lock().unwrap();
+
+        let Some(persisted) = persisted.as_mut() else {
+            return;
+        };
+
+        let content = match serialize_state(state) {
+            Ok(content) => content,
+            Err(err) => {
+                error!("{err:?}");
+                return;
+            }
+        };
+
+        if content == *persisted {
+            return;
+        }
+
+        // The state holds the tasks' secrets
+        match write_private_file(&self.state_file, content.as_bytes()) {
+            Ok(()) => *persisted = content,
+            Err(err) => error!("Failed to persist the daemon's state: {err}"),
+        }
+    }
+
+    /// Stop persisting the state as the daemon exits, only keeping it if tasks were left running
+    pub fn finish(&self, state: &State, keep: bool) {
+        if keep {
+            self.persist(state);
+        }
+
+        *self.persisted.lock().unwrap() = None;
+
+        if !keep {
+            if let Err(err) = fs::remove_file(&self.state_file) {
+                error!("Failed to remove the persisted state: {err}");
+            }
+        }
+    }
+}
+
+/// Get back the tasks of a previous daemon process that didn't exit cleanly
+///
+/// Processes that are still running are either adopted, in which case they are monitored until they exit,
+/// or reported as orphaned.
+pub fn recover_tasks(data_dir: &Path, orphans: OrphanPolicy) -> Result<Option<Vec<TaskWrapper>>> {
+    let state_file = state_file(data_dir);
+
+    if !state_file.exists() {
+        return Ok(None);
+    }
+
+    let content = fs::read_to_string(&state_file).context("Failed to read the persisted state")?;
+
+    let mut persisted = match serde_json::from_str::<PersistedState>(&content) {
+        Ok(persisted) => persisted,
+        Err(err) => {
+            // Keep the file for inspection, but don't prevent the daemon from starting
+            let corrupted = state_file.with_extension("json.corrupted");
+
+            warn!(
+                "Failed to parse the persisted state, starting without recovering tasks (moved to '{}'): {err}",
+                corrupted.display()
+            );
+
+            fs::rename(&state_file, &corrupted)
+                .context("Failed to move the corrupted persisted state")?;
+
+            return Ok(None);
+        }
+    };
+
+    warn!(
+        "Previous daemon process didn't exit cleanly, recovering {} task(s)...",
+        persisted.tasks.len()
+    );
+
+    for wrapper in &persisted.tasks {
+        let mut state = wrapper.state.lock().unwrap();
+
+        state.secrets = persisted
+            .secrets
+            .remove(&wrapper.task.name)
+            .unwrap_or_default();
+
+        // Secrets are missing from the state persisted by older versions, so the output can't be redacted
+        let missing_secrets = wrapper
+            .task
+            .secret_env
+            .iter()
+            .any(|name| !state.secrets.contains_key(name));
+
+        // Output is restored from the log file, without the original timestamps
+        if missing_secrets {
+            warn!(
+                "Output of task '{}' is unavailable as the value of its secrets is unknown.",
+                wrapper.task.name
+            );
+        } else if let Some(log_file) = &state.log_file {
+            if let Ok(content) = fs::read(log_file) {
+                let collected = &content[..content.len().min(state.log_offset as usize)];
+
+                state.output = String::from_utf8_lossy(collected)
+                    .lines()
+                    .map(|line| redact(line, &state.secrets))
+                    .collect();
+            }
+        }
+
+        if !state.status.has_process() {
+            continue;
+        }
+
+        if !state.is_process_alive() {
+            warn!(
+                "Task '{}' (PID {}) exited while the daemon wasn't running.",
+                wrapper.task.name,
+                state.pid.unwrap_or_default()
+            );
+
+            state.ended_at = Some(get_now());
+            state.status = TaskStatus::Exited;
+
+            if let (Some(log_file), false) = (&state.log_file, state.secrets.is_empty()) {
+                if let Err(err) = redact_log_file(log_file, &state.secrets) {
+                    warn!("Failed to redact the secrets from the task's log file: {err:?}");
+                }
+            }
+
+            continue;
+        }
+
+        match orphans {
+            // The output of adopted tasks is collected, which can't be done without redacting it
+            OrphanPolicy::Adopt if !missing_secrets => {
+                warn!(
+                    "Adopting task '{}' (PID {}) left running by the previous daemon process.",
+                    wrapper.task.name,
+                    state.pid.unwrap_or_default()
+                );
+
+                state.adopted = true;
+                state.status = TaskStatus::Running { child: None };
+            }
+
+            OrphanPolicy::Adopt | OrphanPolicy::Report => {
+                warn!(
+                    "Task '{}' (PID {}) was left running by the previous daemon process.",
+                    wrapper.task.name,
+                    state.pid.unwrap_or_default()
+                );
+
+                state.status = TaskStatus::Orphaned;
+            }
+        }
+    }
+
+    Ok(Some(persisted.tasks))
+}
@@ -1,19 +1,55 @@
 use std::{
-    io::{BufRead, BufReader},
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions, Permissions},
+    io::{BufRead, BufReader, Seek, SeekFrom},
+    os::unix::fs::{OpenOptionsExt, PermissionsExt},
+    path::{Path, PathBuf},
     process::Command,
+    sync::{Arc, Mutex, RwLock},
 };
 
-use crate::datetime::get_now;
+use crate::{
+    config::Config,
+    datetime::{format_timestamp, get_now},
+    process::read_proc_stat,
+    secrets::redact,
+    sleep::sleep_ms,
+    task::Task,
+    warn,
+};
+
+use anyhow::{bail, Context, Result};
 
-use anyhow::{Context, Result};
-use command_group::CommandGroup;
+use super::{
+    children::{forget_child, spawn_child},
+    hooks::{hook_log_file, run_hooks, HookContext, HookEvent},
+    task::{notify_status_change, TaskState, TaskStatus, TaskWrapper},
+};
 
-use super::task::{TaskStatus, TaskWrapper};
+/// Run a task's command until it exits, calling `on_spawned` once its process is spawned
+pub fn runner(
+    TaskWrapper { state, task }: TaskWrapper,
+    logs_dir: &Path,
+    config: &RwLock<Config>,
+    on_spawned: impl FnOnce(),
+) -> Result<()> {
+    let secrets = state.lock().unwrap().secrets.clone();
 
-pub static DEFAULT_SHELL_CMD: &str = "/bin/sh -c";
+    // Secrets are not exported, and were not persisted by older versions, so they may be missing
+    if let Some(name) = task
+        .secret_env
+        .iter()
+        .find(|name| !secrets.contains_key(*name))
+    {
+        bail!(
+            "Missing value for secret environment variable {name}, run the task again to provide it"
+        );
+    }
 
-pub fn runner(TaskWrapper { state, task }: TaskWrapper) -> Result<()> {
-    let shell_cmd = task.shell.unwrap_or_else(|| DEFAULT_SHELL_CMD.to_string());
+    let shell_cmd = task
+        .shell
+        .clone()
+        .unwrap_or_else(|| config.read().unwrap().default_shell.value.clone());
 
     let mut shell_cmd_parts = shell_cmd.split(' ');
 
@@ -25,51 +61,217 @@ pub fn runner(TaskWrapper { state, task }: TaskWrapper) -> Result<()> {
 
     cmd.arg(&task.cmd);
 
-    let (reader, writer) = os_pipe::pipe().context("Failed to obtain a pipe")?;
+    // The command's output is written to a file instead of a pipe so tasks can outlive the daemon
+    let log_file = task_log_file(logs_dir, &task.name);
+
+    // The raw output may contain the secrets until the file is redacted when the task completes,
+    // which never happens if the daemon crashes or the task is left running, so only the owner can read it
+    let writer = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .mode(0o600)
+        .open(&log_file)
+        .context("Failed to open the task's log file")?;
+
+    // The file may have been created by a previous run, e.g. when the task is restarted automatically
+    writer
+        .set_permissions(Permissions::from_mode(0o600))
+        .context("Failed to restrict the task's log file permissions")?;
+
+    let mut reader = File::open(&log_file).context("Failed to open the task's log file")?;
+
+    let log_offset = reader
+        .seek(SeekFrom::End(0))
+        .context("Failed to seek the task's log file")?;
 
     cmd.stdout(writer.try_clone().context("Failed to clone the writer")?);
     cmd.stderr(writer);
 
-    if let Some(start_dir) = task.start_dir {
+    if let Some(start_dir) = &task.start_dir {
         cmd.current_dir(start_dir);
     }
 
-    let handle = cmd.group_spawn().context("Failed to spawn the command")?;
+    cmd.envs(&task.env);
+    cmd.envs(&secrets);
 
-    state.lock().unwrap().status = TaskStatus::Running {
-        child: Some(handle),
+    let handle = spawn_child(&mut cmd).context("Failed to spawn the command")?;
+    let pid = handle.id();
+
+    let context = {
+        let mut state = state.lock().unwrap();
+
+        state.pid = Some(pid);
+        state.process_start_time = read_proc_stat(pid).map(|stat| stat.start_time);
+        state.adopted = false;
+        state.started_at = Some(get_now());
+        state.ended_at = None;
+        state.log_file = Some(log_file);
+        state.log_offset = log_offset;
+        state.status = TaskStatus::Running {
+            child: Some(handle),
+        };
+
+        HookContext::new(&state)
     };
 
     drop(cmd);
 
-    let reader = BufReader::new(reader);
+    on_spawned();
 
-    for line in reader.lines() {
-        let line = line.unwrap();
+    run_hooks(HookEvent::Start, &task, context, logs_dir, config);
 
-        state
-            .lock()
-            .expect("Failed to lock the command's output")
-            .output
-            .push(format!("[{}] {}", get_now(), line));
-    }
+    let status = follow_output(&state, BufReader::new(reader), config);
 
-    let status = state
-        .lock()
-        .unwrap()
-        .status
-        .get_child()
-        .context("No child handle in running command's status")?
-        .wait()
-        .context("Failed to run the task's command")?;
-
-    state.lock().unwrap().status = if status.success() {
-        TaskStatus::Success
-    } else {
-        TaskStatus::Failed {
-            code: status.code(),
-        }
+    forget_child(pid);
+
+    complete(&state, &task, status?, logs_dir, config);
+
+    Ok(())
+}
+
+/// Keep collecting the output of a task inherited from a previous daemon process
+pub fn resume_runner(
+    TaskWrapper { state, task }: TaskWrapper,
+    logs_dir: &Path,
+    config: &RwLock<Config>,
+) -> Result<()> {
+    let (log_file, log_offset) = {
+        let state = state.lock().unwrap();
+
+        (
+            state
+                .log_file
+                .clone()
+                .context("No log file for running task")?,
+            state.log_offset,
+        )
     };
 
+    let mut reader = File::open(&log_file).context("Failed to open the task's log file")?;
+
+    reader
+        .seek(SeekFrom::Start(log_offset))
+        .context("Failed to seek the task's log file")?;
+
+    let status = follow_output(&state, BufReader::new(reader), config)?;
+
+    complete(&state, &task, status, logs_dir, config);
+
     Ok(())
 }
+
+fn complete(
+    state: &Arc<Mutex<TaskState>>,
+    task: &Task,
+    status: TaskStatus,
+    logs_dir: &Path,
+    config: &RwLock<Config>,
+) {
+    let mut state = state.lock().unwrap();
+
+    state.ended_at = Some(get_now());
+    state.status = status;
+
+    notify_status_change();
+
+    if !state.secrets.is_empty() {
+        if let Some(log_file) = &state.log_file {
+            if let Err(err) = redact_log_file(log_file, &state.secrets) {
+                warn!("Failed to redact the secrets from the task's log file: {err:?}");
+            }
+        }
+    }
+
+    if let Some(event) = HookEvent::for_status(&state.status) {
+        let context = HookContext::new(&state);
+        drop(state);
+
+        run_hooks(event, task, context, logs_dir, config);
+    }
+}
+
+/// Remove the secrets from a task's log file, which can't be done while the command is writing to it
+pub(super) fn redact_log_file(log_file: &Path, secrets: &BTreeMap<String, String>) -> Result<()> {
+    let content = fs::read(log_file).context("Failed to read the task's log file")?;
+    let content = String::from_utf8_lossy(&content);
+
+    fs::write(log_file, redact(&content, secrets)).context("Failed to write the task's log file")
+}
+
+/// Collect the lines appended to a task's log file until its command exits
+fn follow_output(
+    state: &Arc<Mutex<TaskState>>,
+    mut reader: BufReader<File>,
+    config: &RwLock<Config>,
+) -> Result<TaskStatus> {
+    let mut line = vec![];
+    let mut exited = None;
+
+    loop {
+        let read = reader
+            .read_until(b'\n', &mut line)
+            .context("Failed to read the task's output")?;
+
+        if line.ends_with(b"\n") || (read == 0 && exited.is_some() && !line.is_empty()) {
+            let content = String::from_utf8_lossy(&line);
+
+            let mut state = state.lock().expect("Failed to lock the command's output");
+
+            let content = redact(
+                content.strip_suffix('\n').unwrap_or(&content),
+                &state.secrets,
+            );
+
+            state
+                .output
+                .push(format!("[{}] {}", format_timestamp(get_now()), content));
+
+            state.log_offset += line.len() as u64;
+
+            if let Some(max_lines) = config.read().unwrap().log_retention.value {
+                let excess = state.output.len().saturating_sub(max_lines);
+
+                state.output.drain(..excess);
+                state.discarded_lines += excess;
+            }
+
+            line.clear();
+            continue;
+        }
+
+        if read > 0 {
+            continue;
+        }
+
+        if let Some(status) = exited {
+            return Ok(status);
+        }
+
+        // Check for exit only after reaching the end of file, then do a last pass to get the remaining output
+        exited = state.lock().unwrap().try_wait()?;
+
+        if exited.is_none() {
+            sleep_ms(50);
+        }
+    }
+}
+
+pub fn task_log_file(logs_dir: &Path, task_name: &str) -> PathBuf {
+    let file_name = task_name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+
+    logs_dir.join(format!("{file_name}.log"))
+}
+
+pub fn remove_task_log_file(logs_dir: &Path, task_name: &str) {
+    let _ = fs::remove_file(task_log_file(logs_dir, task_name));
+    let _ = fs::remove_file(hook_log_file(logs_dir, task_name));
+}
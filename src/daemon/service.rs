@@ -1,55 +1,187 @@
-use std::collections::BTreeMap;
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+};
 
-use crate::service;
+use serde::{Deserialize, Serialize};
 
-use super::task::TaskWrapper;
+use crate::{config::Config, service};
 
-service!(
-    daemon (functions) {
-        fn hello() -> u32;
-        fn stop();
+use super::{
+    audit::AuditLog,
+    recovery::StatePersistence,
+    task::{TaskDetails, TaskStatus, TaskSummary, TaskWrapper},
+    DaemonStopArgs, TaskFilter,
+};
 
-        fn tasks() -> super::super::Tasks;
-        fn running_tasks_count() -> usize;
+pub use functions::{kill, list, logs, reload, remove, restart, resume, run, task};
 
-        fn run(task: crate::task::Task);
+service!(
+    daemon (functions) {
+        #[idempotent] #[read_only] fn hello() -> u32;
+        fn stop(wait: bool = false, timeout: Option<u64>, force: bool = false);
+        fn handoff() -> Result<(), String>;
+        fn reload() -> Result<super::super::ReloadReport, String>;
+
+        #[idempotent] #[read_only] fn tasks() -> super::super::Tasks;
+        #[idempotent] #[read_only] fn list(filter: super::super::TaskFilter) -> Vec<super::super::TaskSummary>;
+        #[idempotent] #[read_only] fn task(task_name: String) -> Result<super::super::TaskWrapper, String>;
+        #[idempotent] #[read_only] fn task_details(task_name: String, lines: usize) -> Result<super::super::TaskDetails, String>;
+        #[idempotent] #[read_only] fn running_tasks_count() -> usize;
+        #[idempotent] #[read_only] fn audit_last_hash() -> String;
+
+        fn run(task: crate::task::Task, secrets: std::collections::BTreeMap<String, String> = Default::default()) -> Result<(), String>;
         fn restart(task_name: String) -> Result<(), String>;
+        fn replace(task: crate::task::Task, secrets: std::collections::BTreeMap<String, String> = Default::default()) -> Result<(), String>;
         fn kill(task_name: String) -> Result<(), String>;
         fn remove(task_name: String) -> Result<(), String>;
-        fn logs(task_name: String) -> Result<Vec<String>, String>;
+        #[idempotent] fn acknowledge(task_names: Vec<String>) -> Result<(), String>;
+        fn signal(task_name: String, signal: i32) -> Result<(), String>;
+        #[idempotent] #[read_only] fn logs(task_name: String, from: usize = 0) -> Result<(usize, Vec<String>), String>;
+        #[idempotent] #[read_only] #[long_running] fn wait(task_names: Vec<String>, timeout: Option<u64>) -> Result<std::collections::BTreeMap<String, super::super::TaskStatus>, String>;
     }
 );
 
 mod functions {
-    use std::sync::{Arc, RwLock};
+    use std::{
+        collections::{BTreeMap, BTreeSet},
+        sync::{Arc, RwLock},
+        time::{Duration, Instant},
+    };
+
+    use nix::{
+        sys::signal::{killpg, Signal},
+        unistd::Pid,
+    };
 
     use crate::{
         daemon::{
-            runner::runner,
-            task::{TaskStatus, TaskWrapper},
+            hooks::{run_hooks, HookContext, HookEvent},
+            runner::{remove_task_log_file, resume_runner, runner},
+            task::{
+                notify_status_change, status_changes, wait_for_status_change, TaskDetails,
+                TaskStatus, TaskSummary, TaskWrapper,
+            },
+            DaemonStopArgs, TaskFilter,
         },
+        datetime::set_timestamp_format,
+        ipc::client_disconnected,
         sleep::sleep_ms,
-        task::Task,
+        task::{RestartPolicy, Task},
     };
 
-    use super::Tasks;
+    use super::{ReloadReport, Tasks};
 
     pub type State = RwLock<super::State>;
 
+    static RESTART_DELAY_MS: u64 = 1000;
+    /// Interval at which waiting requests check if their client is still connected
+    static DISCONNECTION_CHECK_INTERVAL_MS: u64 = 500;
+
     pub fn hello(_: Arc<State>) -> u32 {
         std::process::id()
     }
 
-    pub fn stop(state: Arc<State>) {
-        state.write().unwrap().exit = true;
+    pub fn stop(state: Arc<State>, wait: bool, timeout: Option<u64>, force: bool) {
+        state.write().unwrap().exit = Some(DaemonStopArgs {
+            wait,
+            timeout,
+            force,
+        });
+    }
 
-        while !state.read().unwrap().exiting {
-            sleep_ms(20);
+    pub fn handoff(state: Arc<State>) -> Result<(), String> {
+        let mut state = state.write().unwrap();
+
+        if state.exit.is_some() {
+            return Err("Daemon is shutting down".to_string());
         }
+
+        state.handoff = true;
+
+        Ok(())
+    }
+
+    /// Read the configuration again and apply the changes that don't require restarting the daemon
+    pub fn reload(state: Arc<State>) -> Result<ReloadReport, String> {
+        let config = Arc::clone(&state.read().unwrap().config);
+        let mut config = config.write().unwrap();
+
+        let new_config = config.reload().map_err(|err| format!("{err:?}"))?;
+
+        set_timestamp_format(new_config.timestamp_format.value.as_deref())
+            .map_err(|err| format!("{err:?}"))?;
+
+        let mut report = ReloadReport {
+            applied: vec![],
+            requires_restart: vec![],
+        };
+
+        for setting in config.diff(&new_config) {
+            match setting {
+                // The daemon's files can't be moved, and its listeners are only set up when it starts
+                "data_dir" | "http_listen" | "full_access" | "read_only_access" => {
+                    report.requires_restart.push(setting.to_owned())
+                }
+                // Only used by the clients, which read the configuration by themselves
+                "autostart" | "pager" | "connect_timeout" | "request_timeout" => {}
+                _ => report.applied.push(setting.to_owned()),
+            }
+        }
+
+        let data_dir = config.data_dir.clone();
+
+        *config = new_config;
+        config.data_dir = data_dir;
+
+        Ok(report)
     }
 
     pub fn tasks(state: Arc<State>) -> Tasks {
-        state.read().unwrap().tasks.clone()
+        state
+            .read()
+            .unwrap()
+            .tasks
+            .iter()
+            .map(|(name, task)| (name.clone(), task.redacted()))
+            .collect()
+    }
+
+    pub fn list(state: Arc<State>, filter: TaskFilter) -> Vec<TaskSummary> {
+        state
+            .read()
+            .unwrap()
+            .tasks
+            .values()
+            .filter(|task| filter.matches(&task.task, &task.state.lock().unwrap().status))
+            .map(TaskWrapper::summary)
+            .collect()
+    }
+
+    pub fn task(state: Arc<State>, task_name: String) -> Result<TaskWrapper, String> {
+        state
+            .read()
+            .unwrap()
+            .tasks
+            .get(&task_name)
+            .map(TaskWrapper::redacted)
+            .ok_or_else(|| "Provided task was not found".to_string())
+    }
+
+    /// Get a task's details with only the last lines of its output, which is much smaller than the task itself
+    pub fn task_details(
+        state: Arc<State>,
+        task_name: String,
+        lines: usize,
+    ) -> Result<TaskDetails, String> {
+        state
+            .read()
+            .unwrap()
+            .tasks
+            .get(&task_name)
+            .map(|task| task.details(lines))
+            .ok_or_else(|| "Provided task was not found".to_string())
     }
 
     pub fn running_tasks_count(state: Arc<State>) -> usize {
@@ -62,40 +194,384 @@ mod functions {
             .count()
     }
 
-    pub fn run(state: Arc<State>, task: Task) {
+    pub fn audit_last_hash(state: Arc<State>) -> String {
+        state.read().unwrap().audit.last_hash()
+    }
+
+    pub fn run(
+        state: Arc<State>,
+        task: Task,
+        secrets: BTreeMap<String, String>,
+    ) -> Result<(), String> {
         let wrapper = TaskWrapper::new(task);
+        wrapper.state.lock().unwrap().secrets = secrets;
+
+        {
+            // Checked and registered at once, so concurrent calls can't register the same task twice
+            let mut state = state.write().unwrap();
+
+            if state.exit.is_some() {
+                return Err("Daemon is shutting down, new tasks are refused".to_string());
+            }
+
+            if state.tasks.contains_key(&wrapper.task.name) {
+                return Err("A task with this name already exists".to_string());
+            }
+
+            check_dependencies(&state.tasks, &wrapper.task)?;
+
+            register(&mut state, &wrapper);
+        }
+
+        std::thread::spawn(move || supervise(state, wrapper, false));
+
+        Ok(())
+    }
+
+    fn start(state: Arc<State>, wrapper: TaskWrapper) {
+        register(&mut state.write().unwrap(), &wrapper);
+
+        std::thread::spawn(move || supervise(state, wrapper, false));
+    }
+
+    /// Add a task to the registry, replacing the one with the same name if any
+    fn register(state: &mut super::State, wrapper: &TaskWrapper) {
+        // Don't mix the output of the previous runs
+        remove_task_log_file(&state.logs_dir, &wrapper.task.name);
 
         state
-            .write()
-            .unwrap()
             .tasks
             .insert(wrapper.task.name.clone(), wrapper.clone());
+    }
+
+    /// Register the tasks handed off by a previous daemon process and keep supervising them
+    pub fn resume(state: Arc<State>, tasks: Vec<TaskWrapper>) {
+        // Register all tasks first so dependencies can be resolved
+        for wrapper in &tasks {
+            state
+                .write()
+                .unwrap()
+                .tasks
+                .insert(wrapper.task.name.clone(), wrapper.clone());
+        }
 
-        std::thread::spawn(move || {
-            let result = runner(wrapper.clone());
+        for wrapper in tasks {
+            let resumed = match wrapper.state.lock().unwrap().status {
+                TaskStatus::NotStartedYet => Some(false),
+                TaskStatus::Running { child: _ } => Some(true),
+                TaskStatus::Success
+                | TaskStatus::Failed { .. }
+                | TaskStatus::RunnerFailed { .. }
+                | TaskStatus::Exited => None,
+                // Orphaned processes are not monitored
+                TaskStatus::Orphaned => continue,
+            };
+
+            let resumed = match resumed {
+                Some(resumed) => resumed,
+
+                // The task may have been waiting to be restarted
+                None if should_restart(&state, &wrapper) => {
+                    wrapper.state.lock().unwrap().restarts += 1;
+                    false
+                }
+
+                None => continue,
+            };
+
+            let state = Arc::clone(&state);
+            std::thread::spawn(move || supervise(state, wrapper, resumed));
+        }
+    }
 
-            let mut state = state.write().unwrap();
-            let task = state.tasks.get_mut(&wrapper.task.name).unwrap();
+    /// Run a task and restart it depending on its policy
+    ///
+    /// Resumed tasks are already running, in which case their output is collected from where the previous daemon process stopped
+    fn supervise(state: Arc<State>, wrapper: TaskWrapper, mut resumed: bool) {
+        if !resumed {
+            if let Err(message) = wait_for_dependencies(&state, &wrapper) {
+                wrapper.state.lock().unwrap().status = TaskStatus::RunnerFailed { message };
+                notify_status_change();
+                persist(&state);
+                return;
+            }
+        }
+
+        loop {
+            let resuming = std::mem::take(&mut resumed);
+
+            if resuming {
+                // Resumed tasks are already running, so they take a slot anyway
+                state.write().unwrap().active_tasks += 1;
+            } else if let Err(message) = acquire_slot(&state, &wrapper) {
+                let mut task_state = wrapper.state.lock().unwrap();
+
+                if let TaskStatus::NotStartedYet = task_state.status {
+                    task_state.status = TaskStatus::RunnerFailed { message };
+                    notify_status_change();
+                }
+
+                drop(task_state);
+                persist(&state);
+
+                break;
+            }
+
+            let (logs_dir, config) = {
+                let state = state.read().unwrap();
+                (state.logs_dir.clone(), Arc::clone(&state.config))
+            };
+
+            let result = if resuming {
+                resume_runner(wrapper.clone(), &logs_dir, &config)
+            } else {
+                // Persisted as soon as the process is spawned, so it can be found if the daemon crashes
+                runner(wrapper.clone(), &logs_dir, &config, || persist(&state))
+            };
+
+            state.write().unwrap().active_tasks -= 1;
 
             if let Err(err) = result {
-                task.state.lock().unwrap().status = TaskStatus::RunnerFailed {
-                    message: format!("{err:?}"),
+                let context = {
+                    let mut task_state = wrapper.state.lock().unwrap();
+
+                    task_state.status = TaskStatus::RunnerFailed {
+                        message: format!("{err:?}"),
+                    };
+
+                    HookContext::new(&task_state)
                 };
+
+                notify_status_change();
+
+                run_hooks(
+                    HookEvent::Failure,
+                    &wrapper.task,
+                    context,
+                    &logs_dir,
+                    &config,
+                );
             }
-        });
+
+            persist(&state);
+
+            if !should_restart(&state, &wrapper) {
+                break;
+            }
+
+            sleep_ms(RESTART_DELAY_MS);
+
+            // The task may have been killed or removed in the meantime
+            if !should_restart(&state, &wrapper) {
+                break;
+            }
+
+            wrapper.state.lock().unwrap().restarts += 1;
+        }
+    }
+
+    /// Write the state to disk right away instead of waiting for the daemon's next periodic write
+    fn persist(state: &Arc<State>) {
+        let state = state.read().unwrap();
+        state.persistence.persist(&state);
+    }
+
+    /// Wait until less tasks than the configured limit are running, and take a slot
+    fn acquire_slot(state: &Arc<State>, wrapper: &TaskWrapper) -> Result<(), String> {
+        loop {
+            if wrapper.state.lock().unwrap().killed {
+                return Err("Task was killed before it started".to_string());
+            }
+
+            {
+                let mut state = state.write().unwrap();
+
+                if state.exit.is_some() {
+                    return Err("Daemon exited before the task started".to_string());
+                }
+
+                let max_concurrent = state.config.read().unwrap().max_concurrent.value;
+
+                if max_concurrent.is_none_or(|max| state.active_tasks < max) {
+                    state.active_tasks += 1;
+                    return Ok(());
+                }
+            }
+
+            sleep_ms(100);
+        }
+    }
+
+    fn should_restart(state: &Arc<State>, wrapper: &TaskWrapper) -> bool {
+        let registered = {
+            let state = state.read().unwrap();
+
+            state.exit.is_none()
+                && state
+                    .tasks
+                    .get(&wrapper.task.name)
+                    .is_some_and(|task| Arc::ptr_eq(&task.state, &wrapper.state))
+        };
+
+        let task_state = wrapper.state.lock().unwrap();
+
+        registered
+            && !task_state.killed
+            && wrapper
+                .task
+                .restart
+                .should_restart(task_state.status.is_failure())
+    }
+
+    fn wait_for_dependencies(state: &Arc<State>, wrapper: &TaskWrapper) -> Result<(), String> {
+        loop {
+            if wrapper.state.lock().unwrap().killed {
+                return Err("Task was killed before it started".to_string());
+            }
+
+            let mut ready = true;
+
+            {
+                let state = state.read().unwrap();
+
+                if state.exit.is_some() {
+                    return Err("Daemon exited before the task started".to_string());
+                }
+
+                for dependency in &wrapper.task.depends_on {
+                    let Some(dependency_task) = state.tasks.get(dependency) else {
+                        return Err(format!("Dependency '{dependency}' does not exist"));
+                    };
+
+                    let dependency_state = dependency_task.state.lock().unwrap();
+
+                    match dependency_state.status {
+                        TaskStatus::NotStartedYet => ready = false,
+                        TaskStatus::Running { child: _ }
+                        | TaskStatus::Success
+                        | TaskStatus::Orphaned
+                        | TaskStatus::Exited => {}
+                        TaskStatus::Failed { .. } | TaskStatus::RunnerFailed { .. } => {
+                            // The dependency may still succeed after being restarted
+                            if dependency_state.killed
+                                || !dependency_task.task.restart.should_restart(true)
+                            {
+                                return Err(format!("Dependency '{dependency}' failed"));
+                            }
+
+                            ready = false;
+                        }
+                    }
+                }
+            }
+
+            if ready {
+                return Ok(());
+            }
+
+            sleep_ms(100);
+        }
+    }
+
+    /// Make sure a task's dependencies exist and don't depend on the task in turn
+    fn check_dependencies(tasks: &Tasks, task: &Task) -> Result<(), String> {
+        if let Some(unknown) = task
+            .depends_on
+            .iter()
+            .find(|dependency| !tasks.contains_key(*dependency))
+        {
+            return Err(format!("Dependency '{unknown}' does not exist"));
+        }
+
+        let mut to_visit = task.depends_on.clone();
+        let mut visited = BTreeSet::new();
+
+        while let Some(name) = to_visit.pop() {
+            if name == task.name {
+                return Err("Dependencies would form a cycle".to_string());
+            }
+
+            if visited.insert(name.clone()) {
+                if let Some(dependency) = tasks.get(&name) {
+                    to_visit.extend(dependency.task.depends_on.iter().cloned());
+                }
+            }
+        }
+
+        Ok(())
     }
 
     pub fn restart(state: Arc<State>, task_name: String) -> Result<(), String> {
-        let task = {
-            state
-                .write()
-                .unwrap()
-                .tasks
-                .remove(&task_name)
-                .ok_or("Provided task was not found")?
+        respawn(state, &task_name, None, BTreeMap::new())
+    }
+
+    pub fn replace(
+        state: Arc<State>,
+        task: Task,
+        secrets: BTreeMap<String, String>,
+    ) -> Result<(), String> {
+        respawn(state, &task.name.clone(), Some(task), secrets)
+    }
+
+    /// Stop a task if it's running, and start it again with an optional new definition
+    ///
+    /// The previous secrets are kept unless new values are provided.
+    fn respawn(
+        state: Arc<State>,
+        task_name: &str,
+        task: Option<Task>,
+        secrets: BTreeMap<String, String>,
+    ) -> Result<(), String> {
+        if state.read().unwrap().exit.is_some() {
+            return Err("Daemon is shutting down, new tasks are refused".to_string());
+        }
+
+        let existing = state
+            .read()
+            .unwrap()
+            .tasks
+            .get(task_name)
+            .cloned()
+            .ok_or("Provided task was not found")?;
+
+        if let Some(task) = &task {
+            check_dependencies(&state.read().unwrap().tasks, task)?;
+        }
+
+        {
+            let mut task_state = existing.state.lock().unwrap();
+
+            task_state.killed = true;
+
+            if task_state.status.has_process() {
+                task_state.kill().map_err(|err| format!("{err:?}"))?;
+            }
+        }
+
+        while !existing.state.lock().unwrap().status.is_completed() {
+            sleep_ms(20);
+        }
+
+        let (restarts, mut task_secrets) = {
+            let task_state = existing.state.lock().unwrap();
+            (task_state.restarts, task_state.secrets.clone())
         };
 
-        run(state, task.task);
+        task_secrets.extend(secrets);
+
+        let wrapper = TaskWrapper::new(task.unwrap_or(existing.task));
+
+        {
+            let mut task_state = wrapper.state.lock().unwrap();
+
+            task_state.restarts = restarts + 1;
+            task_state.secrets = task_secrets
+                .into_iter()
+                .filter(|(name, _)| wrapper.task.secret_env.contains(name))
+                .collect();
+        }
+
+        start(state, wrapper);
 
         Ok(())
     }
@@ -109,18 +585,55 @@ mod functions {
 
         let mut task_state = task.state.lock().unwrap();
 
-        let handle = task_state
-            .status
-            .get_child()
-            .ok_or("Provided task is not running")?;
+        // Prevent tasks that are waiting to be started or restarted from running again
+        let pending = match task_state.status {
+            TaskStatus::NotStartedYet => true,
+            TaskStatus::Running { child: _ } | TaskStatus::Orphaned => false,
+            TaskStatus::Success
+            | TaskStatus::Failed { .. }
+            | TaskStatus::RunnerFailed { .. }
+            | TaskStatus::Exited => task.task.restart != RestartPolicy::Never && !task_state.killed,
+        };
+
+        if pending {
+            task_state.killed = true;
+            return Ok(());
+        }
+
+        if !task_state.status.has_process() {
+            return Err("Provided task is not running".to_string());
+        }
+
+        task_state.kill().map_err(|err| format!("{err:?}"))?;
 
-        handle.kill().map_err(|err| format!("{err:?}"))?;
+        task_state.killed = true;
 
         Ok(())
     }
 
+    pub fn signal(state: Arc<State>, task_name: String, signal: i32) -> Result<(), String> {
+        let signal = Signal::try_from(signal).map_err(|err| format!("Invalid signal: {err}"))?;
+
+        let tasks = &state.read().unwrap().tasks;
+
+        let task = tasks
+            .get(&task_name)
+            .ok_or("Provided task does not exist")?;
+
+        let task_state = task.state.lock().unwrap();
+
+        let pid = task_state
+            .pid
+            .filter(|_| task_state.status.has_process())
+            .ok_or("Provided task is not running")?;
+
+        // Tasks are spawned as process group leaders
+        killpg(Pid::from_raw(pid as i32), signal).map_err(|err| format!("{err:?}"))
+    }
+
     pub fn remove(state: Arc<State>, task_name: String) -> Result<(), String> {
-        let tasks = &mut state.write().unwrap().tasks;
+        let mut state = state.write().unwrap();
+        let tasks = &mut state.tasks;
 
         let task = tasks
             .get(&task_name)
@@ -133,45 +646,214 @@ mod functions {
                 Err("Cannot remove task as it is waiting to be run".to_string())
             }
 
-            TaskStatus::Running { child: _ } => {
+            TaskStatus::Running { child: _ } | TaskStatus::Orphaned => {
                 Err("Cannot remove task as it is currently running.".to_string())
             }
 
             TaskStatus::Success
-            | TaskStatus::Failed { code: _ }
-            | TaskStatus::RunnerFailed { message: _ } => {
+            | TaskStatus::Failed { .. }
+            | TaskStatus::RunnerFailed { .. }
+            | TaskStatus::Exited => {
                 drop(task_state);
 
                 tasks.remove(&task_name).unwrap();
+                remove_task_log_file(&state.logs_dir, &task_name);
+
                 Ok(())
             }
         }
     }
 
-    pub fn logs(state: Arc<State>, task_name: String) -> Result<Vec<String>, String> {
+    pub fn acknowledge(state: Arc<State>, task_names: Vec<String>) -> Result<(), String> {
+        let tasks = &state.read().unwrap().tasks;
+
+        for task_name in task_names {
+            let task = tasks
+                .get(&task_name)
+                .ok_or_else(|| format!("Task '{task_name}' does not exist"))?;
+
+            task.state.lock().unwrap().acknowledged = true;
+        }
+
+        Ok(())
+    }
+
+    /// Get the task's output from a line index, which includes the lines discarded because of the retention limit
+    ///
+    /// Returns the index of the first line sent, as the discarded ones are skipped.
+    pub fn logs(
+        state: Arc<State>,
+        task_name: String,
+        from: usize,
+    ) -> Result<(usize, Vec<String>), String> {
         let tasks = &state.read().unwrap().tasks;
         let task = tasks.get(&task_name).ok_or("Provided task was not found")?;
 
-        let logs = &task.state.lock().unwrap().output;
+        let task_state = task.state.lock().unwrap();
+
+        let first = from.max(task_state.discarded_lines);
+
+        let lines = task_state
+            .output
+            .get(first - task_state.discarded_lines..)
+            .unwrap_or_default()
+            .to_vec();
+
+        Ok((first, lines))
+    }
+
+    /// Wait for tasks to complete, or for the timeout (in seconds) to expire
+    ///
+    /// Returns the status of each task, which may still be running if the timeout expired
+    pub fn wait(
+        state: Arc<State>,
+        task_names: Vec<String>,
+        timeout: Option<u64>,
+    ) -> Result<BTreeMap<String, TaskStatus>, String> {
+        let deadline = timeout.map(|secs| Instant::now() + Duration::from_secs(secs));
+
+        let task_states = {
+            let tasks = &state.read().unwrap().tasks;
+
+            task_names
+                .into_iter()
+                .map(|task_name| match tasks.get(&task_name) {
+                    Some(task) => Ok((task_name, Arc::clone(&task.state))),
+                    None => Err(format!("Task '{task_name}' does not exist")),
+                })
+                .collect::<Result<Vec<_>, _>>()?
+        };
+
+        // Locks are only held while checking the statuses so other requests are served in the meantime
+        loop {
+            let since = status_changes();
+
+            let completed = task_states
+                .iter()
+                .all(|(_, task_state)| task_state.lock().unwrap().status.is_completed());
+
+            // Nobody would receive the result
+            if completed || client_disconnected() {
+                break;
+            }
+
+            let mut wait_for = Duration::from_millis(DISCONNECTION_CHECK_INTERVAL_MS);
+
+            if let Some(deadline) = deadline {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+
+                if remaining.is_zero() {
+                    break;
+                }
+
+                wait_for = wait_for.min(remaining);
+            }
+
+            wait_for_status_change(since, wait_for);
+        }
+
+        Ok(task_states
+            .into_iter()
+            .map(|(task_name, task_state)| {
+                let status = task_state.lock().unwrap().status.clone_without_child_id();
+                (task_name, status)
+            })
+            .collect())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use serde_json::json;
+
+        use super::check_dependencies;
+        use crate::{daemon::service::Tasks, daemon::task::TaskWrapper, task::Task};
 
-        Ok(logs.clone())
+        fn task(name: &str, depends_on: &[&str]) -> Task {
+            serde_json::from_value(json!({ "name": name, "cmd": "true", "depends_on": depends_on }))
+                .unwrap()
+        }
+
+        fn tasks(tasks: &[Task]) -> Tasks {
+            tasks
+                .iter()
+                .map(|task| (task.name.clone(), TaskWrapper::new(task.clone())))
+                .collect()
+        }
+
+        #[test]
+        fn accept_existing_dependencies() {
+            let tasks = tasks(&[task("a", &[]), task("b", &["a"])]);
+
+            assert!(check_dependencies(&tasks, &task("c", &["a", "b"])).is_ok());
+            assert!(check_dependencies(&tasks, &task("c", &[])).is_ok());
+        }
+
+        #[test]
+        fn reject_unknown_dependency() {
+            let tasks = tasks(&[task("a", &[])]);
+
+            assert_eq!(
+                check_dependencies(&tasks, &task("b", &["a", "unknown"])),
+                Err("Dependency 'unknown' does not exist".to_owned())
+            );
+        }
+
+        #[test]
+        fn reject_dependency_cycle() {
+            // Replacing "a" to depend on "c" would close the loop a -> c -> b -> a
+            let tasks = tasks(&[task("a", &[]), task("b", &["a"]), task("c", &["b"])]);
+
+            assert_eq!(
+                check_dependencies(&tasks, &task("a", &["c"])),
+                Err("Dependencies would form a cycle".to_owned())
+            );
+            assert!(check_dependencies(&tasks, &task("a", &["a"])).is_err());
+        }
     }
 }
 
 pub struct State {
-    pub exit: bool,
-    pub exiting: bool,
+    /// Set when the daemon was asked to stop
+    pub exit: Option<DaemonStopArgs>,
+    /// Set when the daemon was asked to hand its state off to a new process
+    pub handoff: bool,
     pub tasks: Tasks,
+    pub logs_dir: PathBuf,
+    /// Shared with the runners so changes are applied when the configuration is reloaded
+    pub config: Arc<RwLock<Config>>,
+    /// Number of tasks taking a slot, limited by the `max_concurrent` setting
+    pub active_tasks: usize,
+    pub audit: Arc<AuditLog>,
+    pub persistence: StatePersistence,
 }
 
 impl State {
-    pub fn new() -> Self {
+    pub fn new(
+        logs_dir: PathBuf,
+        config: Config,
+        audit: AuditLog,
+        persistence: StatePersistence,
+    ) -> Self {
         Self {
-            exit: false,
-            exiting: false,
+            exit: None,
+            handoff: false,
             tasks: Tasks::default(),
+            logs_dir,
+            config: Arc::new(RwLock::new(config)),
+            active_tasks: 0,
+            audit: Arc::new(audit),
+            persistence,
         }
     }
 }
 
 pub type Tasks = BTreeMap<String, TaskWrapper>;
+
+/// Result of reloading the daemon's configuration
+#[derive(Serialize, Deserialize)]
+pub struct ReloadReport {
+    /// Settings whose new value was applied
+    pub applied: Vec<String>,
+    /// Settings whose new value will only be used after the daemon is restarted
+    pub requires_restart: Vec<String>,
+}
@@ -1,33 +1,77 @@
 use std::{
     fs::{self, OpenOptions},
     io::ErrorKind,
-    os::unix::net::UnixListener,
+    os::{
+        fd::{AsRawFd, RawFd},
+        unix::{fs::PermissionsExt, net::UnixListener},
+    },
     path::{Path, PathBuf},
-    sync::{atomic::Ordering, Arc, Mutex, RwLock}, time::Duration,
+    sync::{atomic::Ordering, Arc, Mutex, RwLock},
+    time::{Duration, Instant},
 };
 
 use anyhow::{bail, Context, Result};
 use daemonize_me::Daemon;
+use listenfd::ListenFd;
+use nix::{
+    sys::wait::{waitpid, WaitPidFlag},
+    unistd::{chown, Gid, Pid},
+};
 use once_cell::sync::Lazy;
+use sd_notify::NotifyState;
+use signal_hook::{
+    consts::{SIGHUP, SIGINT, SIGTERM},
+    iterator::Signals,
+};
 
 use crate::{
+    config::Config,
     daemon::{
+        access::{socket_permissions, AccessPolicy, SocketPermissions},
+        audit::{response_error, AuditLog, Call, Caller},
+        children::is_spawned_child,
+        handoff::{hand_off, Handoff},
+        http::serve_http,
         is_daemon_running,
-        service::{daemon::process, State},
-        DaemonClient, DaemonStartArgs,
+        recovery::{recover_tasks, StatePersistence},
+        service::{
+            daemon::{
+                capabilities, is_long_running, is_read_only, process, RequestContent,
+                ResponseContent,
+            },
+            reload, resume, ReloadReport, State,
+        },
+        task::TaskStatus,
+        DaemonClient, DaemonStartArgs, DaemonStopArgs, OrphanPolicy,
     },
     datetime::get_now_second_precision,
     debug, error, info,
-    ipc::serve_on_socket,
-    logging::PRINT_MESSAGES_DATETIME,
+    ipc::{no_pending_requests, serve_on_socket, wait_for_pending_requests, Peer, RequestFlags},
+    logging::{LOG_TO_STDERR, PRINT_MESSAGES_DATETIME},
+    process::list_processes,
     sleep::sleep_ms,
-    success,
+    success, warn,
 };
 
+static PERSIST_INTERVAL: Duration = Duration::from_secs(1);
+static REAP_INTERVAL: Duration = Duration::from_secs(1);
+static HANDOFF_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
 static SOCKET_FILE_PATH: Lazy<Mutex<Option<PathBuf>>> = Lazy::new(|| Mutex::new(None));
 
-pub fn start_daemon(socket_path: &Path, log_file: &Path, args: &DaemonStartArgs) -> Result<()> {
-    if is_daemon_running(socket_path)? {
+pub fn start_daemon(
+    socket_path: &Path,
+    log_file: &Path,
+    data_dir: &Path,
+    args: &DaemonStartArgs,
+    config: Config,
+) -> Result<()> {
+    // When started through socket activation, systemd already listens on the socket
+    let inherited_socket = ListenFd::from_env()
+        .take_unix_listener(0)
+        .context("Failed to take the socket inherited from systemd")?;
+
+    if inherited_socket.is_none() && is_daemon_running(socket_path)? {
         if args.ignore_started {
             return Ok(());
         }
@@ -35,34 +79,79 @@ pub fn start_daemon(socket_path: &Path, log_file: &Path, args: &DaemonStartArgs)
         bail!("Daemon is already running.");
     }
 
-    let socket = create_socket(socket_path)?;
+    let logs_dir = data_dir.join("logs");
 
-    *SOCKET_FILE_PATH.lock().unwrap() = Some(socket_path.to_path_buf());
+    if !logs_dir.exists() {
+        fs::create_dir_all(&logs_dir).context("Failed to create the tasks' logs directory")?;
+    }
+
+    let handoff = args.resume_from.as_deref().map(Handoff::load).transpose()?;
 
-    let log_file = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(log_file)
-        .context("Failed to open the log file")?;
+    let inherited = inherited_socket.is_some();
 
-    Daemon::new()
-        .stdout(log_file.try_clone().unwrap())
-        .stderr(log_file)
-        .setup_post_fork_parent_hook(fork_exit)
-        .start()
-        .context("Failed to start the daemon")?;
+    let socket = match inherited_socket {
+        Some(socket) => socket,
+        None => create_socket(socket_path, &socket_permissions(&config))?,
+    };
+
+    *SOCKET_FILE_PATH.lock().unwrap() = Some(socket_path.to_path_buf());
+
+    // When resuming, the process is already daemonized and its output is already redirected
+    if handoff.is_none() && !args.foreground {
+        let log_file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(log_file)
+            .context("Failed to open the log file")?;
+
+        Daemon::new()
+            .stdout(log_file.try_clone().unwrap())
+            .stderr(log_file)
+            .setup_post_fork_parent_hook(fork_exit)
+            .start()
+            .context("Failed to start the daemon")?;
+    }
 
     PRINT_MESSAGES_DATETIME.store(true, Ordering::SeqCst);
 
-    match daemon_core(socket_path, socket) {
+    if args.foreground {
+        LOG_TO_STDERR.store(true, Ordering::SeqCst);
+    }
+
+    match daemon_core(
+        socket_path,
+        data_dir,
+        socket,
+        inherited,
+        handoff,
+        args.orphans,
+        config,
+    ) {
         Ok(()) => std::process::exit(0),
-        Err(err) => panic!("Daemon exited with an error: {:?}", err)
+        Err(err) => panic!("Daemon exited with an error: {:?}", err),
     }
 }
 
-fn create_socket(socket_path: &Path) -> Result<UnixListener> {
+fn create_socket(socket_path: &Path, permissions: &SocketPermissions) -> Result<UnixListener> {
     match UnixListener::bind(socket_path) {
-        Ok(socket) => Ok(socket),
+        Ok(socket) => {
+            let mode = match permissions.group {
+                Some(gid) => match chown(socket_path, None, Some(Gid::from_raw(gid))) {
+                    Ok(()) => permissions.mode,
+                    // Only the groups the daemon's user belongs to can be given the socket
+                    Err(err) => {
+                        warn!("Failed to give the socket to group {gid}, opening it to all users instead: {err}");
+                        0o666
+                    }
+                },
+                None => permissions.mode,
+            };
+
+            fs::set_permissions(socket_path, fs::Permissions::from_mode(mode))
+                .context("Failed to set the socket's permissions")?;
+
+            Ok(socket)
+        }
         Err(err) => match err.kind() {
             ErrorKind::AddrInUse => {
                 debug!("Socket file exists but daemon is not running, restarting...");
@@ -70,20 +159,28 @@ fn create_socket(socket_path: &Path) -> Result<UnixListener> {
                 if let Err(err) = fs::remove_file(socket_path) {
                     match err.kind() {
                         // Sometimes the file will vanish just after the existence check, so we ignore "not found" errors
-                        ErrorKind::NotFound => {},
+                        ErrorKind::NotFound => {}
                         // Handle other errors
                         _ => bail!("Failed to remove socket file: {err:?}"),
                     }
                 }
 
-                create_socket(socket_path)
+                create_socket(socket_path, permissions)
             }
             _ => bail!("Failed to connect socket: {err}"),
         },
     }
 }
 
-fn daemon_core(socket_path: &Path, socket: UnixListener) -> Result<()> {
+fn daemon_core(
+    socket_path: &Path,
+    data_dir: &Path,
+    socket: UnixListener,
+    inherited: bool,
+    handoff: Option<Handoff>,
+    orphans: OrphanPolicy,
+    config: Config,
+) -> Result<()> {
     info!(
         "Successfully started the daemon on {}",
         get_now_second_precision()
@@ -91,51 +188,350 @@ fn daemon_core(socket_path: &Path, socket: UnixListener) -> Result<()> {
 
     info!("Launching a separate thread for the socket listener...");
 
-    let state = Arc::new(RwLock::new(State::new()));
+    let http_listen = config.http_listen.value.clone();
+    let access = AccessPolicy::from_config(&config);
+
+    let audit = AuditLog::open(data_dir)?;
+
+    let state = Arc::new(RwLock::new(State::new(
+        data_dir.join("logs"),
+        config,
+        audit,
+        StatePersistence::new(data_dir),
+    )));
+
+    let tasks = match handoff {
+        Some(handoff) => {
+            info!(
+                "Resuming {} task(s) from the previous daemon process...",
+                handoff.tasks.len()
+            );
+
+            Some(handoff.tasks)
+        }
+
+        None => recover_tasks(data_dir, orphans)?,
+    };
+
+    if let Some(tasks) = tasks {
+        resume(Arc::clone(&state), tasks);
+    }
+
+    let state_signals = Arc::clone(&state);
+
+    std::thread::spawn(|| handle_signals(state_signals));
+
+    // Processes whose parent exited are attached to PID 1, which must collect them once they exit
+    if std::process::id() == 1 {
+        let state_reaper = Arc::clone(&state);
+
+        std::thread::spawn(|| reap_zombies(state_reaper));
+    }
+
+    let inherited_socket = inherited.then(|| socket.as_raw_fd());
+
     let state_server = Arc::clone(&state);
 
-    std::thread::spawn(|| serve_on_socket(socket, process, state_server));
+    std::thread::spawn(move || {
+        serve_on_socket(
+            socket,
+            audited_process,
+            RequestFlags {
+                is_read_only,
+                is_long_running,
+            },
+            state_server,
+            capabilities(),
+            move |peer| access.authorize(peer),
+        )
+    });
+
+    if let Some(listen) = http_listen {
+        let data_dir = data_dir.to_path_buf();
+        let state_http = Arc::clone(&state);
+
+        std::thread::spawn(move || {
+            if let Err(err) = serve_http(&listen, &data_dir, state_http) {
+                error!("Failed to serve the HTTP API: {err:?}");
+            }
+        });
+    }
+
+    notify_systemd(NotifyState::Ready);
 
-    daemon_core_loop(socket_path, state);
+    daemon_core_loop(socket_path, data_dir, inherited_socket, state);
 
     info!("Daemon exited.");
 
     Ok(())
 }
 
-fn daemon_core_loop(socket_path: &Path, state: Arc<RwLock<State>>) {
-    info!("Starting the engine...");
+/// Process a request, recording it in the audit log if it may change the daemon's state
+fn audited_process(req: RequestContent, state: Arc<RwLock<State>>, peer: Peer) -> ResponseContent {
+    if is_read_only(&req) {
+        return process(req, state);
+    }
 
-    loop {
-        if state.read().unwrap().exit {
-            info!("Exiting safely as requested...");
+    let audit = Arc::clone(&state.read().unwrap().audit);
+    let call = Call::describe(&req);
+
+    let res = process(req, state);
 
-            state.write().unwrap().exiting = true;
+    audit.record(Caller::socket(peer), call, response_error(&res));
 
-            let tasks = state.read().unwrap().tasks.clone();
+    res
+}
 
-            info!("[Exiting] Terminating {} tasks...", tasks.len());
+fn handle_signals(state: Arc<RwLock<State>>) {
+    let mut signals = match Signals::new([SIGHUP, SIGINT, SIGTERM]) {
+        Ok(signals) => signals,
+        Err(err) => {
+            error!("Failed to listen to signals: {err}");
+            return;
+        }
+    };
 
-            for (i, task) in tasks.values().enumerate() {
-                info!("[Exiting] Terminating task {} / {}...", i + 1, tasks.len());
+    for signal in signals.forever() {
+        if signal != SIGHUP {
+            let mut state = state.write().unwrap();
+
+            if state.exit.is_none() {
+                info!("Received signal {signal}, exiting...");
+
+                state.exit = Some(DaemonStopArgs {
+                    wait: false,
+                    timeout: None,
+                    force: false,
+                });
+            }
 
-                if let Some(child) = task.state.lock().unwrap().status.get_child() {
-                    // TODO: error management
-                    child.kill().unwrap();
+            continue;
+        }
+
+        info!("Received SIGHUP, reloading the configuration...");
+
+        match reload(Arc::clone(&state)) {
+            Ok(ReloadReport {
+                applied,
+                requires_restart,
+            }) => {
+                if applied.is_empty() {
+                    info!("No change to apply.");
+                } else {
+                    info!("Applied new values for: {}", applied.join(", "));
+                }
+
+                if !requires_restart.is_empty() {
+                    warn!(
+                        "New values require restarting the daemon: {}",
+                        requires_restart.join(", ")
+                    );
                 }
             }
 
-            info!("[Exiting] Terminated all tasks.");
-            info!("[Exiting] Now exiting.");
+            Err(err) => error!("Failed to reload the configuration: {err}"),
+        }
+    }
+}
+
+/// Notify systemd of the daemon's state, does nothing when not running as a systemd service
+fn notify_systemd(state: NotifyState) {
+    if let Err(err) = sd_notify::notify(false, &[state]) {
+        error!("Failed to notify systemd: {err}");
+    }
+}
+
+/// Collect the exited processes that are not tasks, as the daemon inherits them when running as PID 1
+fn reap_zombies(state: Arc<RwLock<State>>) {
+    let daemon_pid = std::process::id();
 
-            if let Err(err) = fs::remove_file(socket_path) {
-                error!("Failed to remove the socket file: {err}");
+    loop {
+        sleep_ms(REAP_INTERVAL.as_millis() as u64);
+
+        // Tasks' processes are collected by their runner
+        let task_pids = state
+            .read()
+            .unwrap()
+            .tasks
+            .values()
+            .filter_map(|task| task.state.lock().unwrap().pid)
+            .collect::<Vec<_>>();
+
+        for process in list_processes() {
+            if process.ppid == daemon_pid
+                && process.state == 'Z'
+                && !task_pids.contains(&process.pid)
+                // Including tasks that were just spawned
+                && !is_spawned_child(process.pid)
+            {
+                debug!("Collecting exited process {}...", process.pid);
+
+                if let Err(err) = waitpid(
+                    Pid::from_raw(process.pid as i32),
+                    Some(WaitPidFlag::WNOHANG),
+                ) {
+                    error!("Failed to collect exited process {}: {err}", process.pid);
+                }
             }
+        }
+    }
+}
 
-            break;
+fn daemon_core_loop(
+    socket_path: &Path,
+    data_dir: &Path,
+    inherited_socket: Option<RawFd>,
+    state: Arc<RwLock<State>>,
+) {
+    info!("Starting the engine...");
+
+    let mut last_persist = None::<Instant>;
+
+    let args = loop {
+        // The state is also persisted when tasks start or complete, this catches the other changes
+        if last_persist.is_none_or(|at| at.elapsed() >= PERSIST_INTERVAL) {
+            let state = state.read().unwrap();
+            state.persistence.persist(&state);
+            last_persist = Some(Instant::now());
+        }
+
+        if let Some(args) = state.read().unwrap().exit.clone() {
+            break args;
+        }
+
+        if state.read().unwrap().handoff {
+            info!("Handing off to a new daemon process...");
+
+            let drain_deadline = Instant::now() + HANDOFF_DRAIN_TIMEOUT;
+
+            // Answer the requests being processed, including the one asking for the handoff, then hold the state
+            // so no other request can change it until the process is replaced
+            let state_guard = loop {
+                let drained = wait_for_pending_requests(
+                    drain_deadline.saturating_duration_since(Instant::now()),
+                );
+
+                let state_guard = state.write().unwrap();
+
+                if no_pending_requests() {
+                    break state_guard;
+                }
+
+                if !drained || Instant::now() >= drain_deadline {
+                    warn!("Some requests are still being processed, handing off anyway");
+                    break state_guard;
+                }
+            };
+
+            let Err(err) = hand_off(&state_guard, data_dir, inherited_socket);
+
+            drop(state_guard);
+
+            error!("Failed to hand off to a new daemon process: {err:?}");
+
+            state.write().unwrap().handoff = false;
         }
 
         std::thread::sleep(Duration::from_millis(50));
+    };
+
+    info!("Exiting safely as requested...");
+
+    notify_systemd(NotifyState::Stopping);
+
+    if args.wait {
+        wait_for_running_tasks(&state, args.timeout);
+    }
+
+    let tasks = state.read().unwrap().tasks.clone();
+
+    info!("[Exiting] Terminating {} tasks...", tasks.len());
+
+    let mut detached = false;
+
+    for (i, task) in tasks.values().enumerate() {
+        let mut task_state = task.state.lock().unwrap();
+
+        if !task_state.status.has_process() {
+            continue;
+        }
+
+        if task.task.keep_running && !args.force {
+            info!(
+                "[Exiting] Detaching task {} / {} (PID {})...",
+                i + 1,
+                tasks.len(),
+                task_state.pid.unwrap_or_default()
+            );
+
+            detached = true;
+            continue;
+        }
+
+        info!("[Exiting] Terminating task {} / {}...", i + 1, tasks.len());
+
+        if let Err(err) = task_state.kill() {
+            error!(
+                "[Exiting] Failed to kill task '{}': {err:?}",
+                task.task.name
+            );
+        }
+    }
+
+    info!("[Exiting] Terminated all tasks.");
+
+    // Keep the state so detached tasks are adopted by the next daemon process
+    {
+        let state = state.read().unwrap();
+        state.persistence.finish(&state, detached);
+    }
+
+    info!("[Exiting] Now exiting.");
+
+    // The socket is removed by systemd when it created it
+    if inherited_socket.is_none() {
+        if let Err(err) = fs::remove_file(socket_path) {
+            error!("Failed to remove the socket file: {err}");
+        }
+    }
+}
+
+fn wait_for_running_tasks(state: &Arc<RwLock<State>>, timeout: Option<u64>) {
+    let deadline = timeout.map(|secs| Instant::now() + Duration::from_secs(secs));
+
+    let mut last_running = 0;
+
+    loop {
+        let running = state
+            .read()
+            .unwrap()
+            .tasks
+            .values()
+            // Tasks that keep running will be detached anyway
+            .filter(|task| !task.task.keep_running)
+            .filter(|task| {
+                matches!(
+                    task.state.lock().unwrap().status,
+                    TaskStatus::Running { child: _ }
+                )
+            })
+            .count();
+
+        if running == 0 {
+            break;
+        }
+
+        if running != last_running {
+            info!("[Exiting] Waiting for {running} task(s) to complete...");
+            last_running = running;
+        }
+
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            warn!("[Exiting] Timeout reached with {running} task(s) still running.");
+            break;
+        }
+
+        sleep_ms(100);
     }
 }
 
@@ -147,7 +543,7 @@ fn fork_exit(_parent_pid: i32, _child_pid: i32) -> ! {
         sleep_ms(50);
     }
 
-    let mut client = DaemonClient::connect(socket_path).unwrap();
+    let client = DaemonClient::connect(socket_path).unwrap();
     let daemon_pid = client.hello().unwrap();
 
     success!("Successfully started BJobs daemon!");
@@ -0,0 +1,96 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+/// Unit file to install in systemd's user units directory
+pub struct SystemdUnit {
+    pub file_name: String,
+    pub content: String,
+}
+
+pub fn user_units_dir() -> Result<PathBuf> {
+    Ok(dirs::config_dir()
+        .context("Failed to get path to the configuration directory")?
+        .join("systemd")
+        .join("user"))
+}
+
+pub fn unit_name(profile: Option<&str>) -> String {
+    match profile {
+        Some(profile) => format!("bjobs-{profile}"),
+        None => "bjobs".to_owned(),
+    }
+}
+
+/// Generate a service unit running the daemon in foreground, and a socket unit starting it on the first connection
+pub fn generate_units(data_dir: &Path, profile: Option<&str>) -> Result<[SystemdUnit; 2]> {
+    let exe = std::env::current_exe().context("Failed to get the current binary's path")?;
+
+    let name = unit_name(profile);
+
+    let description = match profile {
+        Some(profile) => format!("BJobs daemon (profile '{profile}')"),
+        None => "BJobs daemon".to_owned(),
+    };
+
+    let bjobs = format!(
+        "{} --custom-data-dir {}",
+        quote(&exe.to_string_lossy()),
+        quote(&data_dir.to_string_lossy())
+    );
+
+    let service = format!(
+        "[Unit]
+Description={description}
+Requires={name}.socket
+After={name}.socket
+
+[Service]
+Type=notify
+ExecStart={bjobs} start --foreground
+ExecStop={bjobs} stop
+ExecReload=/bin/kill -HUP $MAINPID
+# Only stop the daemon, it takes care of its tasks itself
+KillMode=process
+
+[Install]
+WantedBy=default.target
+"
+    );
+
+    let socket = format!(
+        "[Unit]
+Description={description} socket
+
+[Socket]
+ListenStream={}
+SocketMode=0600
+
+[Install]
+WantedBy=sockets.target
+",
+        data_dir.join("bjobs.sock").display()
+    );
+
+    Ok([
+        SystemdUnit {
+            file_name: format!("{name}.service"),
+            content: service,
+        },
+        SystemdUnit {
+            file_name: format!("{name}.socket"),
+            content: socket,
+        },
+    ])
+}
+
+/// Quote an argument for systemd's command lines
+fn quote(arg: &str) -> String {
+    format!(
+        "\"{}\"",
+        arg.replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('%', "%%")
+            .replace('$', "$$")
+    )
+}
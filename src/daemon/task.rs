@@ -1,9 +1,49 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    collections::BTreeMap,
+    os::unix::process::ExitStatusExt,
+    path::PathBuf,
+    process::ExitStatus,
+    sync::{Arc, Condvar, Mutex},
+    time::Duration,
+};
 
+use anyhow::{bail, Context, Result};
 use command_group::GroupChild;
+use nix::{
+    sys::{
+        signal::{killpg, Signal},
+        wait::{waitpid, WaitPidFlag, WaitStatus},
+    },
+    unistd::{getpgid, Pid},
+};
 use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::{datetime::get_now, process::read_proc_stat, task::Task};
+
+/// Number of times a task completed, so waiting for tasks doesn't require polling their status
+static STATUS_CHANGES: Mutex<u64> = Mutex::new(0);
+static STATUS_CHANGED: Condvar = Condvar::new();
+
+/// Wake up the requests waiting for a task to complete
+pub fn notify_status_change() {
+    *STATUS_CHANGES.lock().unwrap() += 1;
+    STATUS_CHANGED.notify_all();
+}
 
-use crate::task::Task;
+/// Get the number of status changes, to wait for the next one with [`wait_for_status_change`]
+pub fn status_changes() -> u64 {
+    *STATUS_CHANGES.lock().unwrap()
+}
+
+/// Wait until a status changed since [`status_changes`] returned `since`, or for the timeout to expire
+pub fn wait_for_status_change(since: u64, timeout: Duration) {
+    let changes = STATUS_CHANGES.lock().unwrap();
+
+    let _ = STATUS_CHANGED
+        .wait_timeout_while(changes, timeout, |changes| *changes == since)
+        .unwrap();
+}
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct TaskWrapper {
@@ -18,12 +58,105 @@ impl TaskWrapper {
             state: Arc::new(Mutex::new(TaskState::new())),
         }
     }
+
+    /// Get a copy of the wrapper with the secrets' values replaced in the task's definition, sharing the same state
+    pub fn redacted(&self) -> Self {
+        Self {
+            task: self.task.redacted(&self.state.lock().unwrap().secrets),
+            state: Arc::clone(&self.state),
+        }
+    }
+
+    pub fn summary(&self) -> TaskSummary {
+        let state = self.state.lock().unwrap();
+
+        TaskSummary {
+            task: self.task.redacted(&state.secrets),
+            status: state.status.clone_without_child_id(),
+            pid: state.pid.filter(|_| state.status.has_process()),
+            started_at: state.started_at,
+            duration_ms: state.duration_ms(),
+            restarts: state.restarts,
+            acknowledged: state.acknowledged,
+        }
+    }
+
+    pub fn details(&self, lines: usize) -> TaskDetails {
+        let state = self.state.lock().unwrap();
+
+        let (exit_code, signal) = match state.status {
+            TaskStatus::Failed { code, signal } => (code, signal),
+            TaskStatus::Success => (Some(0), None),
+            _ => (None, None),
+        };
+
+        let running = state.status.has_process();
+
+        let task = self.task.redacted(&state.secrets);
+
+        TaskDetails {
+            name: task.name,
+            shell: task.shell,
+            cmd: task.cmd,
+            start_dir: task.start_dir,
+            env: task.env,
+            secret_env: task.secret_env,
+            tags: task.tags,
+            on_start: task.on_start,
+            on_success: task.on_success,
+            on_failure: task.on_failure,
+            hook_timeout: task.hook_timeout,
+            status: state.status.clone_without_child_id(),
+            pid: state.pid.filter(|_| running),
+            // Read from the system as the task's process may have moved to another group
+            pgid: state
+                .pid
+                .filter(|_| running)
+                .and_then(|pid| getpgid(Some(Pid::from_raw(pid as i32))).ok())
+                .map(|pgid| pgid.as_raw() as u32),
+            started_at: state.started_at,
+            ended_at: state.ended_at,
+            duration_ms: state.duration_ms(),
+            exit_code,
+            signal,
+            restarts: state.restarts,
+            log_lines: state.output.len(),
+            log_bytes: state.output.iter().map(|line| line.len() + 1).sum(),
+            log_file: state.log_file.clone(),
+            last_lines: state.output[state.output.len().saturating_sub(lines)..].to_vec(),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct TaskState {
     pub status: TaskStatus,
     pub output: Vec<String>,
+    /// Number of output lines discarded because of the retention limit
+    #[serde(default)]
+    pub discarded_lines: usize,
+    pub pid: Option<u32>,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub started_at: Option<OffsetDateTime>,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub ended_at: Option<OffsetDateTime>,
+    pub restarts: usize,
+    pub acknowledged: bool,
+    /// Set when the task was killed on purpose, to prevent it from being restarted
+    pub killed: bool,
+    pub log_file: Option<PathBuf>,
+    /// Position in the log file up to which the output was collected
+    #[serde(default)]
+    pub log_offset: u64,
+    /// Start time of the task's process, used to make sure a PID wasn't reused by another process
+    #[serde(default)]
+    pub process_start_time: Option<u64>,
+    /// Set when the task's process was left by a previous daemon process that crashed
+    #[serde(default)]
+    pub adopted: bool,
+    /// Value of the task's secret environment variables, which are never sent to clients and only persisted in files restricted to the owner
+    #[serde(skip)]
+    pub secrets: BTreeMap<String, String>,
 }
 
 impl TaskState {
@@ -31,8 +164,148 @@ impl TaskState {
         Self {
             status: TaskStatus::NotStartedYet,
             output: vec![],
+            discarded_lines: 0,
+            pid: None,
+            started_at: None,
+            ended_at: None,
+            restarts: 0,
+            acknowledged: false,
+            killed: false,
+            log_file: None,
+            log_offset: 0,
+            process_start_time: None,
+            adopted: false,
+            secrets: BTreeMap::new(),
+        }
+    }
+
+    pub fn clone_without_output(&self) -> Self {
+        Self {
+            status: self.status.clone_without_child_id(),
+            output: vec![],
+            discarded_lines: 0,
+            pid: self.pid,
+            started_at: self.started_at,
+            ended_at: self.ended_at,
+            restarts: self.restarts,
+            acknowledged: self.acknowledged,
+            killed: self.killed,
+            log_file: self.log_file.clone(),
+            log_offset: self.log_offset,
+            process_start_time: self.process_start_time,
+            adopted: self.adopted,
+            // Only used to persist the state, which stores them separately
+            secrets: BTreeMap::new(),
         }
     }
+
+    /// Check if the task's process is still running and is the one that was started, as PIDs may be reused
+    pub fn is_process_alive(&self) -> bool {
+        match (self.pid, self.process_start_time) {
+            (Some(pid), Some(start_time)) => read_proc_stat(pid)
+                .is_some_and(|stat| stat.start_time == start_time && stat.state != 'Z'),
+            _ => false,
+        }
+    }
+
+    pub fn duration_ms(&self) -> Option<u64> {
+        let started_at = self.started_at?;
+        let ended_at = self.ended_at.unwrap_or_else(OffsetDateTime::now_utc);
+
+        Some((ended_at - started_at).whole_milliseconds().max(0) as u64)
+    }
+
+    /// Kill the task's process group
+    ///
+    /// Tasks inherited from a previous daemon process don't have a child handle, so they are killed using their PID
+    pub(super) fn kill(&mut self) -> Result<()> {
+        let pid = self.pid;
+
+        match &mut self.status {
+            TaskStatus::Running { child: Some(child) } => {
+                child.kill().context("Failed to kill the task")
+            }
+
+            TaskStatus::Running { child: None } => {
+                let pid = pid.context("No PID for running task")?;
+
+                // Tasks are spawned as process group leaders
+                killpg(Pid::from_raw(pid as i32), Signal::SIGKILL)
+                    .context("Failed to kill the task")
+            }
+
+            TaskStatus::Orphaned => {
+                let pid = pid.context("No PID for orphaned task")?;
+
+                killpg(Pid::from_raw(pid as i32), Signal::SIGKILL)
+                    .context("Failed to kill the task")?;
+
+                // Orphaned tasks are not monitored, so their status won't be updated otherwise
+                self.ended_at = Some(get_now());
+                self.status = TaskStatus::Failed {
+                    code: None,
+                    signal: Some(Signal::SIGKILL as i32),
+                };
+
+                Ok(())
+            }
+
+            _ => bail!("Task is not running"),
+        }
+    }
+
+    /// Check if the task's command exited without blocking, and get the resulting status
+    pub(super) fn try_wait(&mut self) -> Result<Option<TaskStatus>> {
+        let pid = self.pid;
+
+        let status = match &mut self.status {
+            TaskStatus::Running { child: Some(child) } => child
+                .try_wait()
+                .context("Failed to run the task's command")?,
+
+            // Adopted processes are not children of the daemon, so their exit status can't be known
+            TaskStatus::Running { child: None } if self.adopted => {
+                if self.is_process_alive() {
+                    return Ok(None);
+                }
+
+                return Ok(Some(if self.killed {
+                    TaskStatus::Failed {
+                        code: None,
+                        signal: Some(Signal::SIGKILL as i32),
+                    }
+                } else {
+                    TaskStatus::Exited
+                }));
+            }
+
+            TaskStatus::Running { child: None } => {
+                let pid = pid.context("No PID for running task")?;
+
+                // Inherited tasks are still children of the daemon's process as it keeps the same PID
+                match waitpid(Pid::from_raw(pid as i32), Some(WaitPidFlag::WNOHANG))
+                    .context("Failed to wait for the task's command")?
+                {
+                    WaitStatus::Exited(_, code) => Some(ExitStatus::from_raw(code << 8)),
+                    WaitStatus::Signaled(_, signal, _) => Some(ExitStatus::from_raw(signal as i32)),
+                    _ => None,
+                }
+            }
+
+            _ => bail!("Task is not running"),
+        };
+
+        Ok(status.map(|status| {
+            if status.success() {
+                TaskStatus::Success
+            } else {
+                TaskStatus::Failed {
+                    code: status.code(),
+                    signal: status.signal(),
+                }
+            }
+        }))
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -45,10 +318,15 @@ pub enum TaskStatus {
     Success,
     Failed {
         code: Option<i32>,
+        signal: Option<i32>,
     },
     RunnerFailed {
         message: String,
     },
+    /// Process left running by a previous daemon process that crashed, which isn't monitored
+    Orphaned,
+    /// Adopted process exited, but its exit status is unknown as it wasn't a child of the daemon
+    Exited,
 }
 
 impl TaskStatus {
@@ -57,35 +335,93 @@ impl TaskStatus {
             Self::NotStartedYet => Self::NotStartedYet,
             Self::Running { child: _ } => Self::Running { child: None },
             Self::Success => Self::Success,
-            Self::Failed { code } => Self::Failed { code: *code },
+            Self::Failed { code, signal } => Self::Failed {
+                code: *code,
+                signal: *signal,
+            },
             Self::RunnerFailed { message } => Self::RunnerFailed {
                 message: message.clone(),
             },
+            Self::Orphaned => Self::Orphaned,
+            Self::Exited => Self::Exited,
         }
     }
 
     pub fn is_completed(&self) -> bool {
         match self {
-            TaskStatus::NotStartedYet | TaskStatus::Running { child: _ } => false,
+            TaskStatus::NotStartedYet | TaskStatus::Running { child: _ } | TaskStatus::Orphaned => {
+                false
+            }
             TaskStatus::Success
-            | TaskStatus::Failed { code: _ }
-            | TaskStatus::RunnerFailed { message: _ } => true,
+            | TaskStatus::Failed { .. }
+            | TaskStatus::RunnerFailed { .. }
+            | TaskStatus::Exited => true,
         }
     }
 
     pub fn is_failure(&self) -> bool {
         match self {
-            TaskStatus::NotStartedYet | TaskStatus::Running { child: _ } | TaskStatus::Success => {
-                false
-            }
-            TaskStatus::Failed { code: _ } | TaskStatus::RunnerFailed { message: _ } => true,
+            TaskStatus::NotStartedYet
+            | TaskStatus::Running { child: _ }
+            | TaskStatus::Success
+            | TaskStatus::Orphaned
+            | TaskStatus::Exited => false,
+            TaskStatus::Failed { .. } | TaskStatus::RunnerFailed { .. } => true,
         }
     }
 
-    pub(super) fn get_child(&mut self) -> Option<&mut GroupChild> {
-        match self {
-            TaskStatus::Running { child } => Some(child.as_mut().unwrap()),
-            _ => None,
-        }
+    /// Check if the task has a process, which may not be monitored
+    pub fn has_process(&self) -> bool {
+        matches!(
+            self,
+            TaskStatus::Running { child: _ } | TaskStatus::Orphaned
+        )
     }
 }
+
+#[derive(Serialize, Deserialize)]
+pub struct TaskSummary {
+    pub task: Task,
+    pub status: TaskStatus,
+    pub pid: Option<u32>,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub started_at: Option<OffsetDateTime>,
+    pub duration_ms: Option<u64>,
+    pub restarts: usize,
+    pub acknowledged: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TaskDetails {
+    pub name: String,
+    pub shell: Option<String>,
+    pub cmd: String,
+    pub start_dir: Option<PathBuf>,
+    pub env: BTreeMap<String, String>,
+    #[serde(default)]
+    pub secret_env: Vec<String>,
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub on_start: Option<String>,
+    #[serde(default)]
+    pub on_success: Option<String>,
+    #[serde(default)]
+    pub on_failure: Option<String>,
+    #[serde(default)]
+    pub hook_timeout: Option<u64>,
+    pub status: TaskStatus,
+    pub pid: Option<u32>,
+    pub pgid: Option<u32>,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub started_at: Option<OffsetDateTime>,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub ended_at: Option<OffsetDateTime>,
+    pub duration_ms: Option<u64>,
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+    pub restarts: usize,
+    pub log_lines: usize,
+    pub log_bytes: usize,
+    pub log_file: Option<PathBuf>,
+    pub last_lines: Vec<String>,
+}
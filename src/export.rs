@@ -0,0 +1,63 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    daemon::{TaskState, Tasks},
+    task::Task,
+};
+
+/// Registry dump produced by `bjobs export`
+#[derive(Serialize, Deserialize)]
+pub struct Export {
+    pub tasks: Vec<ExportedTask>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ExportedTask {
+    pub task: Task,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state: Option<TaskState>,
+}
+
+impl Export {
+    pub fn new(tasks: Tasks, with_status: bool, with_logs: bool) -> Self {
+        Self {
+            tasks: tasks
+                .into_values()
+                .map(|wrapper| ExportedTask {
+                    task: wrapper.task,
+                    state: (with_status || with_logs).then(|| {
+                        let mut state = std::mem::replace(
+                            &mut *wrapper.state.lock().unwrap(),
+                            TaskState::new(),
+                        );
+
+                        if !with_logs {
+                            state.output.clear();
+                        }
+
+                        state
+                    }),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ConflictStrategy {
+    /// Keep the existing task
+    Skip,
+    /// Kill and replace the existing task
+    Replace,
+    /// Import the task under a new name
+    Rename,
+}
+
+/// Find a name that isn't used by any existing task by adding a numeric suffix
+pub fn available_name(name: &str, is_taken: impl Fn(&str) -> bool) -> String {
+    (2..)
+        .map(|i| format!("{name}-{i}"))
+        .find(|candidate| !is_taken(candidate))
+        .unwrap()
+}
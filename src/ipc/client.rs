@@ -1,63 +1,449 @@
 use std::{
-    io::{BufRead, BufReader, Write},
+    collections::HashMap,
+    fmt::{self, Display},
+    io::{BufRead, BufReader, ErrorKind, Write},
     marker::PhantomData,
+    net::Shutdown,
     os::unix::net::UnixStream,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{self, RecvTimeoutError, Sender},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, Instant},
 };
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
+use once_cell::sync::Lazy;
 use serde::{de::DeserializeOwned, Serialize};
 
-use super::{Request, Response};
+use crate::{
+    config::{DEFAULT_CONNECT_TIMEOUT, DEFAULT_REQUEST_TIMEOUT},
+    debug,
+    sleep::sleep_ms,
+};
+
+use super::{
+    read_frame, write_frame, Codec, Handshake, HandshakeReply, HandshakeRequest, HandshakeResponse,
+    PartialResponse, Request, Response, PERMISSION_DENIED, PROTOCOL_VERSION,
+};
+
+static TIMEOUTS: Lazy<RwLock<Timeouts>> = Lazy::new(|| {
+    RwLock::new(Timeouts {
+        connect: Duration::from_secs(DEFAULT_CONNECT_TIMEOUT),
+        request: Some(Duration::from_secs(DEFAULT_REQUEST_TIMEOUT)),
+    })
+});
+
+#[derive(Clone, Copy)]
+pub struct Timeouts {
+    /// Maximum time to connect and perform the handshake, or to wait for the server to come back after losing the connection
+    pub connect: Duration,
+    /// Maximum time to wait for the response to a request
+    pub request: Option<Duration>,
+}
+
+/// How a request must be sent
+#[derive(Clone, Copy)]
+pub struct CallOptions {
+    /// Send the request again after reconnecting if the connection was lost
+    pub idempotent: bool,
+    /// Wait for the response without timeout
+    pub long_running: bool,
+}
+
+/// Set the timeouts used by all clients
+pub fn set_client_timeouts(timeouts: Timeouts) {
+    *TIMEOUTS.write().unwrap() = timeouts;
+}
+
+/// Errors related to the connection with the server, as opposed to the ones returned by the server itself
+#[derive(Debug)]
+pub enum ConnectionError {
+    NotRunning {
+        name: &'static str,
+    },
+    Unresponsive {
+        name: &'static str,
+        timeout: Duration,
+    },
+    Lost {
+        name: &'static str,
+        reason: String,
+    },
+}
+
+impl Display for ConnectionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectionError::NotRunning { name } => write!(f, "The {name} is not running."),
+            ConnectionError::Unresponsive { name, timeout } => write!(
+                f,
+                "The {name} is running but didn't respond within {} second(s), it may be stuck: check its logs or restart it.",
+                timeout.as_secs_f64()
+            ),
+            ConnectionError::Lost { name, reason } => {
+                write!(f, "Lost the connection to the {name}: {reason}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConnectionError {}
 
+/// Client able to send several requests at once on the same connection
+///
+/// Responses are read from a separate thread and dispatched to the pending requests by their ID,
+/// so they can be sent back by the server in any order.
+///
+/// When the connection is lost, idempotent requests are sent again after reconnecting.
 pub struct SocketClient<A: Serialize, B: DeserializeOwned> {
-    stream: UnixStream,
+    /// Name of the server, used in error messages
+    name: &'static str,
+    socket_path: PathBuf,
+    timeouts: Timeouts,
+    conn: RwLock<Arc<Connection<B>>>,
+    reconnect: AtomicBool,
+    next_id: AtomicU64,
     _req: PhantomData<A>,
-    _res: PhantomData<B>,
 }
 
-impl<A: Serialize, B: DeserializeOwned> SocketClient<A, B> {
-    pub fn new(stream: UnixStream) -> Self {
-        Self {
-            stream,
+struct Connection<B> {
+    stream: Mutex<UnixStream>,
+    codec: Codec,
+    server: Arc<HandshakeResponse>,
+    pending: Arc<Mutex<Pending<B>>>,
+}
+
+struct Pending<B> {
+    requests: HashMap<u64, Sender<Result<B, String>>>,
+    /// Set when the connection was closed, with the reason why
+    closed: Option<String>,
+}
+
+impl<A: Serialize, B: DeserializeOwned + Send + 'static> SocketClient<A, B> {
+    pub fn connect(name: &'static str, socket_path: &Path) -> Result<Self> {
+        let timeouts = *TIMEOUTS.read().unwrap();
+
+        let conn = Connection::open(name, socket_path, timeouts)?;
+
+        Ok(Self {
+            name,
+            socket_path: socket_path.to_path_buf(),
+            timeouts,
+            conn: RwLock::new(Arc::new(conn)),
+            reconnect: AtomicBool::new(true),
+            next_id: AtomicU64::new(1),
             _req: PhantomData,
-            _res: PhantomData,
+        })
+    }
+
+    /// Informations sent by the server during the last handshake
+    pub fn server(&self) -> Arc<HandshakeResponse> {
+        Arc::clone(&self.conn.read().unwrap().server)
+    }
+
+    /// Don't reconnect when the connection is lost, e.g. when the server is expected to exit
+    pub fn disable_reconnect(&self) {
+        self.reconnect.store(false, Ordering::SeqCst);
+    }
+
+    pub fn send_unchecked(&self, req: A, options: CallOptions) -> Result<B> {
+        let conn = Arc::clone(&self.conn.read().unwrap());
+
+        match self.send_on(&conn, &req, options) {
+            Err(err) if options.idempotent && self.reconnect.load(Ordering::SeqCst) => {
+                let Some(ConnectionError::Lost { reason, .. }) = err.downcast_ref() else {
+                    return Err(err);
+                };
+
+                debug!(
+                    "Lost the connection to the {} ({reason}), reconnecting...",
+                    self.name
+                );
+
+                let conn = self.reconnect(&conn)?;
+
+                self.send_on(&conn, &req, options)
+            }
+
+            result => result,
         }
     }
 
-    pub fn send_unchecked(&mut self, req: A) -> Result<B> {
+    fn send_on(&self, conn: &Connection<B>, req: &A, options: CallOptions) -> Result<B> {
         let req = Request {
-            id: rand::random(),
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
             content: req,
         };
 
-        let mut req_str =
-            serde_json::to_string(&req).context("Failed to stringify request for server")?;
+        let payload = conn
+            .codec
+            .encode(&req)
+            .context("Failed to encode request for server")?;
+
+        let (sender, receiver) = mpsc::channel();
+
+        {
+            let mut pending = conn.pending.lock().unwrap();
+
+            if let Some(reason) = &pending.closed {
+                return Err(self.lost(reason.clone()));
+            }
+
+            pending.requests.insert(req.id, sender);
+        }
+
+        if let Err(err) = write_frame(&mut *conn.stream.lock().unwrap(), &payload) {
+            conn.pending.lock().unwrap().requests.remove(&req.id);
+
+            return Err(match err.kind() {
+                ErrorKind::WouldBlock | ErrorKind::TimedOut => self.unresponsive(),
+                _ => self.lost(format!("failed to transmit the request: {err}")),
+            });
+        }
+
+        let timeout = self.timeouts.request.filter(|_| !options.long_running);
+
+        let response = match timeout {
+            Some(timeout) => receiver.recv_timeout(timeout),
+            None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+
+        match response {
+            Ok(result) => result.map_err(|err| anyhow!("Server returned an error: {err}")),
+
+            Err(RecvTimeoutError::Timeout) => {
+                conn.pending.lock().unwrap().requests.remove(&req.id);
+                Err(self.unresponsive())
+            }
+
+            Err(RecvTimeoutError::Disconnected) => {
+                let reason = conn.pending.lock().unwrap().closed.clone();
+
+                Err(self.lost(reason.unwrap_or_else(|| "no response received".to_owned())))
+            }
+        }
+    }
+
+    /// Replace a lost connection, waiting for the server to come back (e.g. when it's restarting)
+    fn reconnect(&self, lost: &Arc<Connection<B>>) -> Result<Arc<Connection<B>>> {
+        let mut conn = self.conn.write().unwrap();
+
+        // Another request may have reconnected in the meantime
+        if !Arc::ptr_eq(&conn, lost) {
+            return Ok(Arc::clone(&conn));
+        }
+
+        let deadline = Instant::now() + self.timeouts.connect;
+
+        let new_conn = loop {
+            match Connection::open(self.name, &self.socket_path, self.timeouts) {
+                Ok(new_conn) => break new_conn,
+
+                Err(err) => match err.downcast_ref() {
+                    Some(ConnectionError::NotRunning { .. }) if Instant::now() < deadline => {
+                        sleep_ms(50);
+                    }
+
+                    _ => return Err(err),
+                },
+            }
+        };
+
+        *conn = Arc::new(new_conn);
+
+        Ok(Arc::clone(&conn))
+    }
+
+    fn unresponsive(&self) -> anyhow::Error {
+        anyhow!(ConnectionError::Unresponsive {
+            name: self.name,
+            timeout: self.timeouts.request.unwrap_or(self.timeouts.connect),
+        })
+    }
+
+    fn lost(&self, reason: String) -> anyhow::Error {
+        anyhow!(ConnectionError::Lost {
+            name: self.name,
+            reason,
+        })
+    }
+}
+
+impl<B: DeserializeOwned + Send + 'static> Connection<B> {
+    /// Connect to the server and perform the handshake, which chooses the codec used for the next messages
+    fn open(name: &'static str, socket_path: &Path, timeouts: Timeouts) -> Result<Self> {
+        let timeout = timeouts.connect;
+
+        let mut stream = match UnixStream::connect(socket_path) {
+            Ok(stream) => stream,
+            Err(err) => match err.kind() {
+                ErrorKind::NotFound | ErrorKind::ConnectionRefused => {
+                    bail!(ConnectionError::NotRunning { name })
+                }
+                _ => bail!("Failed to handle the socket file: {err}"),
+            },
+        };
+
+        stream
+            .set_read_timeout(Some(timeout))
+            .and_then(|()| stream.set_write_timeout(Some(timeout)))
+            .context("Failed to set the socket's timeouts")?;
+
+        let mut reader = BufReader::new(
+            stream
+                .try_clone()
+                .context("Failed to clone the server's stream")?,
+        );
+
+        let handshake = Handshake {
+            protocol_version: PROTOCOL_VERSION,
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            codecs: Codec::preferred()?
+                .into_iter()
+                .map(|codec| codec.name().to_owned())
+                .collect(),
+        };
+
+        let mut req_str = serde_json::to_string(&Request {
+            id: 0,
+            content: HandshakeRequest::handshake(handshake),
+        })
+        .context("Failed to stringify handshake for server")?;
 
         // Message separator
         req_str.push('\n');
 
-        self.stream
+        let io_error = |err: std::io::Error| match err.kind() {
+            ErrorKind::WouldBlock | ErrorKind::TimedOut => {
+                anyhow!(ConnectionError::Unresponsive { name, timeout })
+            }
+            _ => anyhow!("Failed to perform the handshake with the server: {err}"),
+        };
+
+        match stream
             .write_all(req_str.as_bytes())
-            .context("Failed to transmit request to server")?;
+            .and_then(|()| stream.flush())
+        {
+            Ok(()) => {}
+            // The server may already have refused the connection, in which case its answer can still be read
+            Err(err) if err.kind() == ErrorKind::BrokenPipe => {}
+            Err(err) => return Err(io_error(err)),
+        }
 
-        self.stream
-            .flush()
-            .context("Failed to flush the server's stream")?;
+        let mut response = String::new();
 
-        let response = BufReader::new(&self.stream)
-            .lines()
-            .next()
-            .context("Failed to get a response from the server")?
-            .context("Failed to retrieve the server's response")?;
+        reader.read_line(&mut response).map_err(io_error)?;
 
-        let response = serde_json::from_str::<Response<B>>(&response)
-            .context("Failed to parse server's response")?;
+        if response.is_empty() {
+            bail!("Failed to get a handshake from the server");
+        }
+
+        let response = serde_json::from_str::<Response<HandshakeReply>>(&response)
+            .context("Failed to parse server's handshake")?;
 
-        // TODO: queue system with untreated responses
-        assert_eq!(req.id, response.for_id);
+        if let Err(err) = &response.result {
+            if err.starts_with(PERMISSION_DENIED) {
+                bail!("The {name} refused the connection: {err}");
+            }
+        }
 
-        response
+        // Servers predating handshakes fail to parse the request
+        let HandshakeReply::handshake(server) = response
             .result
             .map_err(|err| anyhow!("Server returned an error: {err}"))
+            .with_context(|| {
+                format!("The {name} is running an older version of bjobs, please restart it")
+            })?;
+
+        if server.protocol_version != PROTOCOL_VERSION {
+            bail!(
+                "The {name} (PID {}) is version {} (protocol {}) while this client is version {} (protocol {}), please restart it",
+                server.pid,
+                server.version,
+                server.protocol_version,
+                env!("CARGO_PKG_VERSION"),
+                PROTOCOL_VERSION
+            );
+        }
+
+        let codec = Codec::from_name(&server.codec)
+            .with_context(|| format!("Server chose unknown codec '{}'", server.codec))?;
+
+        // The reading thread waits for responses indefinitely, as requests have their own timeout
+        stream
+            .set_read_timeout(None)
+            .and_then(|()| stream.set_write_timeout(timeouts.request))
+            .context("Failed to set the socket's timeouts")?;
+
+        let pending = Arc::new(Mutex::new(Pending {
+            requests: HashMap::new(),
+            closed: None,
+        }));
+
+        let pending_reader = Arc::clone(&pending);
+
+        std::thread::spawn(move || read_responses(reader, codec, pending_reader));
+
+        Ok(Self {
+            stream: Mutex::new(stream),
+            codec,
+            server: Arc::new(server),
+            pending,
+        })
+    }
+}
+
+impl<B> Drop for Connection<B> {
+    fn drop(&mut self) {
+        // Stops the thread reading the responses
+        let _ = self.stream.lock().unwrap().shutdown(Shutdown::Both);
     }
 }
+
+fn read_responses<B: DeserializeOwned>(
+    mut reader: BufReader<UnixStream>,
+    codec: Codec,
+    pending: Arc<Mutex<Pending<B>>>,
+) {
+    let reason = loop {
+        let message = match read_frame(&mut reader) {
+            Ok(Some(message)) => message,
+            Ok(None) => break "the server closed the connection".to_owned(),
+            Err(err) => break format!("failed to read the server's response: {err}"),
+        };
+
+        let (id, result) = match codec.decode::<Response<B>>(&message) {
+            Ok(Response { for_id, result }) => (for_id, result),
+
+            Err(err) => match codec.decode::<PartialResponse>(&message) {
+                Ok(PartialResponse { for_id }) => (
+                    for_id,
+                    Err(format!("Failed to parse server's response: {err:#}")),
+                ),
+
+                Err(_) => {
+                    debug!("Failed to parse response from the server: {err:#}");
+                    continue;
+                }
+            },
+        };
+
+        match pending.lock().unwrap().requests.remove(&id) {
+            // The request may have been abandoned in the meantime
+            Some(sender) => {
+                let _ = sender.send(result);
+            }
+
+            None => debug!("Received a response for unknown request {id}"),
+        }
+    };
+
+    let mut pending = pending.lock().unwrap();
+
+    // Dropping the senders makes pending requests fail
+    pending.requests.clear();
+    pending.closed = Some(reason);
+}
@@ -0,0 +1,100 @@
+use anyhow::{bail, Context, Result};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Format of the messages exchanged after the handshake
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    /// Human-readable, useful for debugging
+    Json,
+    /// Compact binary format
+    MessagePack,
+}
+
+impl Codec {
+    pub const ALL: [Codec; 2] = [Codec::MessagePack, Codec::Json];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Codec::Json => "json",
+            Codec::MessagePack => "msgpack",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|codec| codec.name() == name)
+    }
+
+    /// Codecs the client wants to use, by order of preference
+    ///
+    /// A single codec can be forced with the `BJOBS_IPC_CODEC` environment variable.
+    pub fn preferred() -> Result<Vec<Self>> {
+        match std::env::var("BJOBS_IPC_CODEC") {
+            Ok(name) => match Self::from_name(&name) {
+                Some(codec) => Ok(vec![codec]),
+                None => bail!("Unknown IPC codec '{name}' in environment variable BJOBS_IPC_CODEC"),
+            },
+            Err(_) => Ok(Self::ALL.to_vec()),
+        }
+    }
+
+    pub fn encode<T: Serialize>(self, value: &T) -> Result<Vec<u8>> {
+        match self {
+            Codec::Json => serde_json::to_vec(value).context("Failed to encode message as JSON"),
+            Codec::MessagePack => {
+                rmp_serde::to_vec_named(value).context("Failed to encode message as MessagePack")
+            }
+        }
+    }
+
+    pub fn decode<T: DeserializeOwned>(self, bytes: &[u8]) -> Result<T> {
+        match self {
+            Codec::Json => serde_json::from_slice(bytes).context("Failed to decode JSON message"),
+            Codec::MessagePack => {
+                rmp_serde::from_slice(bytes).context("Failed to decode MessagePack message")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use serde::{Deserialize, Serialize};
+
+    use super::Codec;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Message {
+        Empty,
+        Fields {
+            name: String,
+            lines: Vec<String>,
+            timeout: Option<u64>,
+            env: BTreeMap<String, String>,
+        },
+    }
+
+    #[test]
+    fn codecs_round_trip() {
+        let messages = [
+            Message::Empty,
+            Message::Fields {
+                name: "task".to_owned(),
+                lines: vec!["a".to_owned(), "".to_owned()],
+                timeout: None,
+                env: BTreeMap::from([("KEY".to_owned(), "value".to_owned())]),
+            },
+        ];
+
+        for codec in Codec::ALL {
+            for message in &messages {
+                let bytes = codec.encode(message).unwrap();
+                assert_eq!(&codec.decode::<Message>(&bytes).unwrap(), message);
+            }
+
+            assert!(codec.decode::<Message>(b"\xff\x00garbage").is_err());
+            assert!(Codec::from_name(codec.name()) == Some(codec));
+        }
+    }
+}
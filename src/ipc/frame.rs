@@ -0,0 +1,88 @@
+use std::io::{self, ErrorKind, Read, Write};
+
+/// Frames bigger than this are considered corrupted
+pub const MAX_FRAME_SIZE: usize = 64 * 1024 * 1024;
+
+/// Write a message prefixed by its length, as a 32-bit big-endian integer
+pub fn write_frame(writer: &mut impl Write, payload: &[u8]) -> io::Result<()> {
+    let len = u32::try_from(payload.len())
+        .ok()
+        .filter(|len| *len as usize <= MAX_FRAME_SIZE)
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "Message is too large"))?;
+
+    writer.write_all(&len.to_be_bytes())?;
+    writer.write_all(payload)?;
+    writer.flush()
+}
+
+/// Read a message written by [`write_frame`], returns `None` if the stream was closed
+pub fn read_frame(reader: &mut impl Read) -> io::Result<Option<Vec<u8>>> {
+    let mut len = [0; 4];
+
+    match reader.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err),
+    }
+
+    let len = u32::from_be_bytes(len) as usize;
+
+    if len > MAX_FRAME_SIZE {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("Frame of {len} bytes is too large"),
+        ));
+    }
+
+    // The length can't be trusted, so memory is only allocated as the bytes arrive
+    let mut payload = vec![];
+    reader.take(len as u64).read_to_end(&mut payload)?;
+
+    if payload.len() < len {
+        return Err(io::Error::new(
+            ErrorKind::UnexpectedEof,
+            "Stream was closed in the middle of a frame",
+        ));
+    }
+
+    Ok(Some(payload))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::ErrorKind;
+
+    use super::{read_frame, write_frame, MAX_FRAME_SIZE};
+
+    #[test]
+    fn frame_round_trip() {
+        let mut stream = vec![];
+        write_frame(&mut stream, b"first").unwrap();
+        write_frame(&mut stream, b"").unwrap();
+
+        let mut reader = stream.as_slice();
+        assert_eq!(read_frame(&mut reader).unwrap().unwrap(), b"first");
+        assert_eq!(read_frame(&mut reader).unwrap().unwrap(), b"");
+        assert!(read_frame(&mut reader).unwrap().is_none());
+    }
+
+    #[test]
+    fn frame_too_large() {
+        let err = write_frame(&mut vec![], &vec![0; MAX_FRAME_SIZE + 1]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+        let len = (MAX_FRAME_SIZE as u32 + 1).to_be_bytes();
+        let err = read_frame(&mut len.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn frame_truncated() {
+        let mut stream = vec![];
+        write_frame(&mut stream, b"truncated").unwrap();
+        stream.pop();
+
+        let err = read_frame(&mut stream.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+}
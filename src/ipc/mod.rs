@@ -1,7 +1,11 @@
 mod client;
+mod codec;
+mod frame;
 mod server;
 mod service;
 
 pub use client::*;
+pub use codec::*;
+pub use frame::*;
 pub use server::*;
 pub use service::*;
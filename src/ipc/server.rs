@@ -1,22 +1,140 @@
 use std::{
-    io::{BufRead, BufReader, Write},
-    os::unix::net::{UnixListener, UnixStream},
-    sync::Arc,
+    cell::RefCell,
+    io::{BufRead, BufReader, Read, Write},
+    os::{
+        fd::AsRawFd,
+        unix::net::{UnixListener, UnixStream},
+    },
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Condvar, Mutex,
+    },
     time::Duration,
 };
 
+use anyhow::{bail, Context, Result};
+use nix::sys::socket::{getsockopt, sockopt::PeerCredentials};
 use serde::{de::DeserializeOwned, Serialize};
 
-use crate::{error, sleep::sleep_ms};
+use crate::error;
 
-use super::{PartialRequest, Request, Response};
+use super::{
+    read_frame, write_frame, Codec, HandshakeReply, HandshakeRequest, HandshakeResponse,
+    PartialRequest, Request, Response, MAX_FRAME_SIZE, PROTOCOL_VERSION,
+};
+
+/// Prefix of the errors returned to clients lacking the required access
+pub static PERMISSION_DENIED: &str = "Permission denied";
+
+/// Maximum time for a client to send its handshake once connected
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Handshakes are small, anything bigger is rejected
+const MAX_HANDSHAKE_SIZE: u64 = 64 * 1024;
+
+/// Requests of a single client processed at the same time, the next ones being refused
+const MAX_CONCURRENT_REQUESTS: usize = 64;
+
+/// Access granted to a client
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Denied,
+    /// Only requests flagged as read-only can be processed
+    ReadOnly,
+    Full,
+}
+
+/// Credentials of the process on the other side of a socket, provided by the kernel
+#[derive(Clone, Copy)]
+pub struct Peer {
+    pub pid: i32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+/// Flags of the requests, checked by the server without knowing the service
+pub struct RequestFlags<A> {
+    /// Can be processed for clients with a read-only access
+    pub is_read_only: fn(&A) -> bool,
+    /// May wait for something for an unbounded time, so isn't waited for before a handoff
+    pub is_long_running: fn(&A) -> bool,
+}
+
+impl<A> Clone for RequestFlags<A> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<A> Copy for RequestFlags<A> {}
+
+thread_local! {
+    /// Set while the client that sent the request being processed by the current thread is connected
+    static CLIENT_CONNECTED: RefCell<Option<Arc<AtomicBool>>> = const { RefCell::new(None) };
+}
+
+/// Check if the client that sent the request being processed disconnected, e.g. to stop waiting for something on its behalf
+pub fn client_disconnected() -> bool {
+    CLIENT_CONNECTED.with(|connected| {
+        connected
+            .borrow()
+            .as_ref()
+            .is_some_and(|connected| !connected.load(Ordering::SeqCst))
+    })
+}
+
+/// Number of requests being processed or answered, long-running ones excepted
+static PENDING_REQUESTS: Mutex<usize> = Mutex::new(0);
+static PENDING_REQUESTS_CHANGED: Condvar = Condvar::new();
+
+/// Wait until every request received so far, long-running ones excepted, has been answered
+///
+/// Returns `false` if some are still pending once the timeout elapsed.
+pub fn wait_for_pending_requests(timeout: Duration) -> bool {
+    let pending = PENDING_REQUESTS.lock().unwrap();
+
+    let (pending, _) = PENDING_REQUESTS_CHANGED
+        .wait_timeout_while(pending, timeout, |pending| *pending > 0)
+        .unwrap();
+
+    *pending == 0
+}
+
+/// Check if every request received so far, long-running ones excepted, has been answered
+pub fn no_pending_requests() -> bool {
+    *PENDING_REQUESTS.lock().unwrap() == 0
+}
 
-pub fn serve_on_socket<A: DeserializeOwned, B: Serialize, S: Send + Sync + 'static>(
+fn update_pending_requests(update: impl FnOnce(&mut usize)) {
+    update(&mut PENDING_REQUESTS.lock().unwrap());
+    PENDING_REQUESTS_CHANGED.notify_all();
+}
+
+pub fn peer_credentials(stream: &UnixStream) -> nix::Result<Peer> {
+    let creds = getsockopt(stream.as_raw_fd(), PeerCredentials)?;
+
+    Ok(Peer {
+        pid: creds.pid(),
+        uid: creds.uid(),
+        gid: creds.gid(),
+    })
+}
+
+pub fn serve_on_socket<
+    A: DeserializeOwned + Send + 'static,
+    B: Serialize,
+    S: Send + Sync + 'static,
+>(
     listener: UnixListener,
-    process: impl Fn(A, Arc<S>) -> B + Send + Sync + 'static,
+    process: impl Fn(A, Arc<S>, Peer) -> B + Send + Sync + 'static,
+    flags: RequestFlags<A>,
     state: Arc<S>,
+    capabilities: Vec<String>,
+    authorize: impl Fn(&Peer) -> Access + Send + Sync + 'static,
 ) -> ! {
     let process = Arc::new(process);
+    let capabilities = Arc::new(capabilities);
+    let authorize = Arc::new(authorize);
 
     for client in listener.incoming() {
         let client = match client {
@@ -34,75 +152,310 @@ pub fn serve_on_socket<A: DeserializeOwned, B: Serialize, S: Send + Sync + 'stat
 
         let process = Arc::clone(&process);
         let state = Arc::clone(&state);
-        std::thread::spawn(move || serve_client(client, process, state));
+        let capabilities = Arc::clone(&capabilities);
+        let authorize = Arc::clone(&authorize);
+
+        std::thread::spawn(move || {
+            let peer = match peer_credentials(&client) {
+                Ok(peer) => peer,
+                Err(err) => {
+                    error!("Failed to get the client's credentials: {err}");
+                    return;
+                }
+            };
+
+            let access = authorize(&peer);
+
+            serve_client(client, process, flags, state, &capabilities, peer, access)
+        });
     }
 
     unreachable!()
 }
 
-fn serve_client<A: DeserializeOwned, B: Serialize, S>(
-    mut client: UnixStream,
-    process: Arc<impl Fn(A, Arc<S>) -> B>,
+fn serve_client<A: DeserializeOwned + Send + 'static, B: Serialize, S: Send + Sync + 'static>(
+    client: UnixStream,
+    process: Arc<impl Fn(A, Arc<S>, Peer) -> B + Send + Sync + 'static>,
+    flags: RequestFlags<A>,
     state: Arc<S>,
+    capabilities: &[String],
+    peer: Peer,
+    access: Access,
 ) {
-    loop {
-        let mut message = String::new();
+    let writer = match client.try_clone() {
+        Ok(writer) => Arc::new(Mutex::new(writer)),
+        Err(err) => {
+            error!("Failed to clone the client's stream: {err}");
+            return;
+        }
+    };
 
-        if let Err(err) = BufReader::new(&client).read_line(&mut message) {
-            error!(
-                "Failed to read message from the client (waiting before retrying): {:?}",
-                err
-            );
-            sleep_ms(5000);
+    // Nothing is read from clients that aren't allowed to connect, so they can't make the daemon hold their data
+    if access == Access::Denied {
+        if let Err(err) = refuse_client(&writer) {
+            error!("Failed to refuse the client's connection: {err:?}");
         }
 
-        if message.is_empty() {
-            break;
+        return;
+    }
+
+    let mut reader = BufReader::new(client);
+
+    let codec = match answer_handshake(&mut reader, &writer, capabilities) {
+        Ok(Some(codec)) => codec,
+        Ok(None) => return,
+        Err(err) => {
+            error!("Failed to perform the handshake with the client: {err:?}");
+            return;
         }
+    };
 
-        let res = match serde_json::from_str::<Request<A>>(&message) {
-            Ok(Request { id, content }) => Response {
-                for_id: id,
-                result: Ok(process(content, Arc::clone(&state))),
-            },
+    let connected = Arc::new(AtomicBool::new(true));
+    let processing = Arc::new(AtomicUsize::new(0));
 
-            Err(err) => match serde_json::from_str::<PartialRequest>(&message) {
-                Ok(PartialRequest { id }) => Response {
-                    for_id: id,
-                    result: Err(format!("Failed to parse client request: {err}")),
-                },
+    loop {
+        let message = match read_frame(&mut reader) {
+            Ok(Some(message)) => message,
+            Ok(None) => break,
+            Err(err) => {
+                error!("Failed to read message from the client: {err}");
+                break;
+            }
+        };
+
+        match codec.decode::<Request<A>>(&message) {
+            Ok(Request { id, content })
+                if access == Access::ReadOnly && !(flags.is_read_only)(&content) =>
+            {
+                send_response::<B>(
+                    &writer,
+                    codec,
+                    &Response {
+                        for_id: id,
+                        result: Err(format!(
+                            "{PERMISSION_DENIED}: this user only has a read-only access"
+                        )),
+                    },
+                )
+            }
+
+            Ok(Request { id, .. })
+                if processing.load(Ordering::SeqCst) >= MAX_CONCURRENT_REQUESTS =>
+            {
+                send_response::<B>(
+                    &writer,
+                    codec,
+                    &Response {
+                        for_id: id,
+                        result: Err(format!(
+                            "Too many requests are being processed for this client, at most {MAX_CONCURRENT_REQUESTS} at a time"
+                        )),
+                    },
+                )
+            }
+
+            // Requests are processed concurrently, so a slow one doesn't block the others
+            Ok(Request { id, content }) => {
+                let process = Arc::clone(&process);
+                let state = Arc::clone(&state);
+                let writer = Arc::clone(&writer);
+                let connected = Arc::clone(&connected);
+                let processing = Arc::clone(&processing);
+
+                processing.fetch_add(1, Ordering::SeqCst);
+
+                // Counted before being processed, so a handoff it requests waits for its response to be sent
+                let pending = !(flags.is_long_running)(&content);
+
+                if pending {
+                    update_pending_requests(|pending| *pending += 1);
+                }
+
+                std::thread::spawn(move || {
+                    CLIENT_CONNECTED.with(|current| *current.borrow_mut() = Some(connected));
+
+                    let res = Response {
+                        for_id: id,
+                        result: Ok(process(content, state, peer)),
+                    };
+
+                    send_response(&writer, codec, &res);
+
+                    if pending {
+                        update_pending_requests(|pending| *pending -= 1);
+                    }
+
+                    processing.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+
+            Err(err) => match codec.decode::<PartialRequest>(&message) {
+                Ok(PartialRequest { id }) => send_response::<B>(
+                    &writer,
+                    codec,
+                    &Response {
+                        for_id: id,
+                        result: Err(format!("Failed to parse client request: {err:#}")),
+                    },
+                ),
 
                 Err(_) => {
-                    error!("Failed to parse request from client: {err}");
+                    error!("Failed to parse request from client: {err:#}");
                     short_sleep();
-                    continue;
                 }
             },
-        };
+        }
+    }
 
-        let mut res = match serde_json::to_string(&res) {
-            Ok(res) => res,
-            Err(err) => {
-                error!("Failed to stringify response for client: {err}");
-                short_sleep();
-                continue;
-            }
-        };
+    connected.store(false, Ordering::SeqCst);
+}
+
+/// Tell a client it isn't allowed to connect, as an answer to the handshake it is about to send
+fn refuse_client(writer: &Mutex<UnixStream>) -> Result<()> {
+    send_line(
+        writer,
+        &Response::<()> {
+            // Clients always send their handshake with this ID
+            for_id: 0,
+            result: Err(format!(
+                "{PERMISSION_DENIED}: this user isn't allowed to connect"
+            )),
+        },
+    )
+}
+
+/// Answer the handshake sent as a line of JSON by the client, and choose the codec for the next messages
+///
+/// Returns `None` if the client didn't start with a handshake, which happens with older versions.
+fn answer_handshake(
+    reader: &mut BufReader<UnixStream>,
+    writer: &Mutex<UnixStream>,
+    capabilities: &[String],
+) -> Result<Option<Codec>> {
+    let mut message = String::new();
+
+    reader
+        .get_ref()
+        .set_read_timeout(Some(HANDSHAKE_TIMEOUT))
+        .context("Failed to set the client's read timeout")?;
+
+    reader
+        .by_ref()
+        .take(MAX_HANDSHAKE_SIZE)
+        .read_line(&mut message)
+        .context("Failed to read the client's handshake")?;
+
+    if message.is_empty() {
+        return Ok(None);
+    }
+
+    if !message.ends_with('\n') {
+        bail!("Handshake is incomplete or larger than {MAX_HANDSHAKE_SIZE} bytes");
+    }
+
+    // Requests can take any time to arrive once the client is connected
+    reader
+        .get_ref()
+        .set_read_timeout(None)
+        .context("Failed to reset the client's read timeout")?;
+
+    let (id, handshake) = match serde_json::from_str::<Request<HandshakeRequest>>(&message) {
+        Ok(Request {
+            id,
+            content: HandshakeRequest::handshake(handshake),
+        }) => (id, handshake),
+
+        Err(err) => {
+            let PartialRequest { id } = serde_json::from_str(&message)
+                .with_context(|| format!("Failed to parse the client's handshake: {err}"))?;
+
+            send_line(
+                writer,
+                &Response::<()> {
+                    for_id: id,
+                    result: Err(format!(
+                        "Expected a handshake, the client is probably running an older version: {err}"
+                    )),
+                },
+            )?;
+
+            return Ok(None);
+        }
+    };
+
+    // Clients that don't list codecs only support JSON
+    let codec = handshake
+        .codecs
+        .iter()
+        .find_map(|name| Codec::from_name(name))
+        .unwrap_or(Codec::Json);
+
+    send_line(
+        writer,
+        &Response {
+            for_id: id,
+            result: Ok(HandshakeReply::handshake(HandshakeResponse {
+                protocol_version: PROTOCOL_VERSION,
+                version: env!("CARGO_PKG_VERSION").to_owned(),
+                pid: std::process::id(),
+                capabilities: capabilities.to_vec(),
+                codec: codec.name().to_owned(),
+            })),
+        },
+    )?;
 
-        // Message separator
-        res.push('\n');
+    Ok(Some(codec))
+}
+
+fn send_line<T: Serialize>(writer: &Mutex<UnixStream>, res: &Response<T>) -> Result<()> {
+    let mut res = serde_json::to_string(res).context("Failed to stringify response for client")?;
+
+    // Message separator
+    res.push('\n');
+
+    let mut writer = writer.lock().unwrap();
 
-        if let Err(err) = client.write_all(res.as_bytes()) {
-            error!("Failed to transmit response to client: {err}");
-            short_sleep();
-            continue;
+    writer
+        .write_all(res.as_bytes())
+        .and_then(|()| writer.flush())
+        .context("Failed to transmit response to client")
+}
+
+fn send_response<B: Serialize>(writer: &Mutex<UnixStream>, codec: Codec, res: &Response<B>) {
+    let encoded = codec.encode(res).and_then(|encoded| {
+        if encoded.len() > MAX_FRAME_SIZE {
+            bail!(
+                "Response of {} bytes exceeds the limit of {MAX_FRAME_SIZE} bytes",
+                encoded.len()
+            );
         }
 
-        if let Err(err) = client.flush() {
-            error!("Failed to flush the client's stream: {err}");
-            short_sleep();
-            continue;
+        Ok(encoded)
+    });
+
+    let res = match encoded {
+        Ok(res) => res,
+        Err(err) => {
+            error!("Failed to encode response for client: {err:#}");
+
+            // Report the error so the client doesn't wait for a response that will never come
+            let res = Response::<B> {
+                for_id: res.for_id,
+                result: Err(format!("Failed to encode the response: {err:#}")),
+            };
+
+            match codec.encode(&res) {
+                Ok(res) => res,
+                Err(err) => {
+                    error!("Failed to encode error response for client: {err:#}");
+                    return;
+                }
+            }
         }
+    };
+
+    if let Err(err) = write_frame(&mut *writer.lock().unwrap(), &res) {
+        error!("Failed to transmit response to client: {err}");
     }
 }
 
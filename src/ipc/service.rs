@@ -1,51 +1,120 @@
 use serde::{Deserialize, Serialize};
 
+use super::Codec;
+
+/// Generate a service's requests and responses, a function processing them on the server side, and a client
+///
+/// Arguments with a default value (`name: Type = value`) are sent as an `Option` so they can be omitted by clients predating them,
+/// the same as `Option` arguments, while the generated clients always provide them.
+///
+/// Functions can be flagged with:
+/// * `#[idempotent]` to be sent again after reconnecting when the connection is lost
+/// * `#[long_running]` to wait for their response without a timeout, and not to be waited for before a handoff
+/// * `#[read_only]` to be available to clients with a read-only access
 #[macro_export]
 macro_rules! service {
     ($service_name:ident ($mod:ident) {
-        $(fn $fn_name:ident($($fn_arg_name:ident: $fn_arg_type:ty)?)$( -> $fn_ret_type:ty)?;)+
+        $($(#[$flag:ident])* fn $fn_name:ident($($arg_name:ident: $arg_type:ty $(= $arg_default:expr)?),* $(,)?)$( -> $fn_ret_type:ty)?;)+
     }) => {
         pub mod $service_name {
-            use ::std::sync::Arc;
+            use ::std::{path::Path, sync::Arc};
 
             use ::serde::{Serialize, Deserialize};
             use ::anyhow::{bail, Result};
 
-            use $crate::ipc::SocketClient;
+            use $crate::ipc::{CallOptions, SocketClient};
 
             use super::$mod::{self as functions, State};
 
             #[derive(Serialize, Deserialize)]
             #[allow(non_camel_case_types)]
             pub enum RequestContent {
-                $($fn_name $({ $fn_arg_name: $fn_arg_type })?),+
+                $($fn_name { $($arg_name: $crate::service!(@arg_ty $arg_type $(= $arg_default)?)),* }),+
             }
 
             #[derive(Serialize, Deserialize)]
-            #[allow(non_camel_case_types)]
+            // Responses are only built to be serialized right away
+            #[allow(non_camel_case_types, clippy::large_enum_variant)]
             pub enum ResponseContent {
                 $($fn_name($crate::service!(@ty $($fn_ret_type)?))),+
             }
 
             mod handlers {
-                $(pub(super) fn $fn_name(#[allow(unused_variables)] state: super::Arc<super::State>$(, $fn_arg_name: $fn_arg_type)?)$( -> $fn_ret_type)? {
-                    super::functions::$fn_name(state$(, $fn_arg_name)?)
+                $(pub(super) fn $fn_name(#[allow(unused_variables)] state: super::Arc<super::State>, $($arg_name: $crate::service!(@arg_ty $arg_type $(= $arg_default)?)),*)$( -> $fn_ret_type)? {
+                    $(let $arg_name = $crate::service!(@arg_value $arg_name $(= $arg_default)?);)*
+
+                    super::functions::$fn_name(state, $($arg_name),*)
                 })+
             }
 
             pub fn process(req: RequestContent, state: Arc<State>) -> ResponseContent {
                 match req {
-                    $(RequestContent::$fn_name $({ $fn_arg_name })? => ResponseContent::$fn_name(handlers::$fn_name(state, $($fn_arg_name)?))),+
+                    $(RequestContent::$fn_name { $($arg_name),* } => ResponseContent::$fn_name(handlers::$fn_name(state, $($arg_name),*))),+
+                }
+            }
+
+            /// Check if a request can be processed for clients with a read-only access
+            pub fn is_read_only(req: &RequestContent) -> bool {
+                match req {
+                    $(RequestContent::$fn_name { .. } => $crate::service!(@read_only $($flag)*)),+
                 }
             }
 
+            /// Check if a request may wait for something for an unbounded time
+            pub fn is_long_running(req: &RequestContent) -> bool {
+                match req {
+                    $(RequestContent::$fn_name { .. } => $crate::service!(@long_running $($flag)*)),+
+                }
+            }
+
+            /// Name of the functions provided by the service, sent to clients during the handshake
+            pub fn capabilities() -> Vec<String> {
+                vec![$(stringify!($fn_name).to_owned()),+]
+            }
+
             pub struct Client {
-                pub inner: SocketClient<RequestContent, ResponseContent>
+                inner: SocketClient<RequestContent, ResponseContent>,
             }
 
             impl Client {
-                $(pub fn $fn_name(&mut self$(, $fn_arg_name: $fn_arg_type)?) -> Result<$crate::service!(@ty $($fn_ret_type)?)> {
-                    match self.inner.send_unchecked(RequestContent::$fn_name $({ $fn_arg_name })?)? {
+                /// Connect to the server and perform the handshake, ensuring it talks the same protocol as the client
+                pub fn new(socket_path: &Path) -> Result<Self> {
+                    Ok(Self {
+                        inner: SocketClient::connect(stringify!($service_name), socket_path)?,
+                    })
+                }
+
+                /// Version of bjobs the server is running
+                pub fn server_version(&self) -> String {
+                    self.inner.server().version.clone()
+                }
+
+                /// Check if the server supports a function, as it may be running an older version
+                pub fn supports(&self, fn_name: &str) -> bool {
+                    self.inner.server().capabilities.iter().any(|capability| capability == fn_name)
+                }
+
+                /// Don't reconnect when the connection is lost, e.g. when the server is expected to exit
+                pub fn disable_reconnect(&self) {
+                    self.inner.disable_reconnect();
+                }
+
+                $(pub fn $fn_name(&self, $($arg_name: $arg_type),*) -> Result<$crate::service!(@ty $($fn_ret_type)?)> {
+                    if !self.supports(stringify!($fn_name)) {
+                        bail!(
+                            "The {} is version {} which doesn't support '{}', please restart it",
+                            stringify!($service_name),
+                            self.server_version(),
+                            stringify!($fn_name)
+                        );
+                    }
+
+                    let options = CallOptions {
+                        idempotent: $crate::service!(@idempotent $($flag)*),
+                        long_running: $crate::service!(@long_running $($flag)*),
+                    };
+
+                    match self.inner.send_unchecked(RequestContent::$fn_name { $($arg_name: $crate::service!(@arg_wrap $arg_name $(= $arg_default)?)),* }, options)? {
                         ResponseContent::$fn_name ($crate::service!(@pat $($fn_ret_type)? => output)) => Ok($crate::service!(@expr $($fn_ret_type)? => output)),
 
                         #[allow(unreachable_patterns)]
@@ -56,6 +125,27 @@ macro_rules! service {
         }
     };
 
+    (@arg_ty $type:ty = $default:expr) => { Option<$type> };
+    (@arg_ty $type:ty) => { $type };
+
+    (@arg_value $name:ident = $default:expr) => { $name.unwrap_or_else(|| $default) };
+    (@arg_value $name:ident) => { $name };
+
+    (@arg_wrap $name:ident = $default:expr) => { Some($name) };
+    (@arg_wrap $name:ident) => { $name };
+
+    (@idempotent) => { false };
+    (@idempotent idempotent $($rest:ident)*) => { true };
+    (@idempotent $other:ident $($rest:ident)*) => { $crate::service!(@idempotent $($rest)*) };
+
+    (@long_running) => { false };
+    (@long_running long_running $($rest:ident)*) => { true };
+    (@long_running $other:ident $($rest:ident)*) => { $crate::service!(@long_running $($rest)*) };
+
+    (@read_only) => { false };
+    (@read_only read_only $($rest:ident)*) => { true };
+    (@read_only $other:ident $($rest:ident)*) => { $crate::service!(@read_only $($rest)*) };
+
     (@ty $type:ty) => { $type };
     (@ty) => { () };
 
@@ -66,6 +156,52 @@ macro_rules! service {
     (@expr => $expr:expr) => { () };
 }
 
+/// Version of the protocol, to increase whenever the format of existing requests or responses changes
+///
+/// Adding new functions doesn't require a new version, as clients check if the server supports them.
+pub static PROTOCOL_VERSION: u32 = 4;
+
+/// First request sent by a client to a server
+///
+/// It is sent as a line of JSON, the messages after it being length-prefixed frames encoded with the codec chosen by the server.
+/// Its format, and the one of its response, must only get new optional fields so clients and servers of any version can understand each other.
+#[derive(Serialize, Deserialize)]
+pub struct Handshake {
+    pub protocol_version: u32,
+    pub version: String,
+    /// Name of the codecs supported by the client, by order of preference
+    #[serde(default)]
+    pub codecs: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct HandshakeResponse {
+    pub protocol_version: u32,
+    pub version: String,
+    pub pid: u32,
+    /// Name of the functions supported by the server
+    pub capabilities: Vec<String>,
+    #[serde(default = "default_codec")]
+    pub codec: String,
+}
+
+fn default_codec() -> String {
+    Codec::Json.name().to_owned()
+}
+
+/// Wrappers giving the handshake the same format as the requests and responses of older versions
+#[derive(Serialize, Deserialize)]
+#[allow(non_camel_case_types)]
+pub enum HandshakeRequest {
+    handshake(Handshake),
+}
+
+#[derive(Serialize, Deserialize)]
+#[allow(non_camel_case_types)]
+pub enum HandshakeReply {
+    handshake(HandshakeResponse),
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Request<T> {
     pub id: u64,
@@ -82,3 +218,8 @@ pub struct Response<T> {
     pub for_id: u64,
     pub result: Result<T, String>,
 }
+
+#[derive(Deserialize)]
+pub struct PartialResponse {
+    pub for_id: u64,
+}
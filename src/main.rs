@@ -2,32 +2,60 @@
 #![forbid(unused_must_use)]
 
 mod cmd;
+mod config;
 mod daemon;
+mod export;
 mod ipc;
+mod profiles;
+mod secrets;
 mod task;
+mod taskfile;
+mod tui;
 mod utils;
 
 use utils::logging::PRINT_DEBUG_MESSAGES;
 pub use utils::*;
 
-use std::{fs, sync::atomic::Ordering};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, Read},
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
 
 use anyhow::{anyhow, bail, Context, Result};
 use clap::Parser;
-use colored::Colorize;
-use tabular::{row, Table};
+use colored::{ColoredString, Colorize};
+use tabular::{row, Row, Table};
 
 use crate::{
-    cmd::{Action, CheckArgs, Cmd, KillArgs, LogsArgs, RemoveArgs, RestartArgs, RunArgs},
-    daemon::{is_daemon_running, start_daemon, DaemonClient, TaskStatus, TaskWrapper},
+    cmd::{
+        Action, ApplyArgs, AuditArgs, CheckArgs, Cmd, Column, ConfigAction, ExportArgs, ImportArgs,
+        KillArgs, ListArgs, LogsArgs, RemoveArgs, RestartArgs, RunArgs, ShowArgs, SortBy, WaitArgs,
+    },
+    config::{Config, Setting, Source},
+    daemon::{
+        audit_file, generate_units, hook_log_file, is_daemon_running, read_audit_key,
+        read_audit_log, start_daemon, unit_name, user_units_dir, verify_audit_log, AuditEntry,
+        AuditLogContent, DaemonAction, DaemonClient, InstallUnitArgs, ReloadReport, TaskDetails,
+        TaskFilter, TaskStatus, TaskSummary,
+    },
+    datetime::{format_duration_ms, format_timestamp, second_precision, set_timestamp_format},
+    export::{available_name, ConflictStrategy, Export},
+    ipc::{set_client_timeouts, Timeouts},
     paging::run_pager,
+    profiles::{list_profiles, profile_data_dir},
+    secrets::{prompt_secrets, read_secrets},
     sleep::sleep_ms,
     task::Task,
+    taskfile::TaskFile,
+    tui::run_dashboard,
 };
 
 fn main() -> ! {
     let code = match inner_main() {
-        Ok(()) => 0,
+        Ok(code) => code,
         Err(err) => {
             error_anyhow!(err);
             1
@@ -38,7 +66,7 @@ fn main() -> ! {
     std::process::exit(code);
 }
 
-fn inner_main() -> Result<()> {
+fn inner_main() -> Result<i32> {
     debug!("Entered inner main.");
 
     let cmd = Cmd::parse();
@@ -47,11 +75,31 @@ fn inner_main() -> Result<()> {
         PRINT_DEBUG_MESSAGES.store(true, Ordering::SeqCst);
     }
 
-    let data_dir = match cmd.custom_data_dir {
-        Some(data_dir) => data_dir,
-        None => dirs::data_local_dir()
-            .context("Failed to get path to local data directory")?
-            .join("bjobs"),
+    let mut config = Config::load()?;
+    config.data_dir.set_from_cli(cmd.custom_data_dir);
+
+    set_timestamp_format(config.timestamp_format.value.as_deref())?;
+
+    set_client_timeouts(Timeouts {
+        connect: Duration::from_secs(config.connect_timeout.value),
+        request: config.request_timeout.value.map(Duration::from_secs),
+    });
+
+    // Daemons are spawned with the profile's data directory while inheriting the environment,
+    // so the profile from the environment must not be applied again on top of it
+    let env_profile = || match config.data_dir.source {
+        Source::Cli => None,
+        _ => std::env::var("BJOBS_PROFILE").ok(),
+    };
+
+    let profile = cmd
+        .profile
+        .or_else(env_profile)
+        .filter(|profile| !profile.is_empty());
+
+    let data_dir = match &profile {
+        Some(profile) => profile_data_dir(&config.data_dir.value, profile)?,
+        None => config.data_dir.value.clone(),
     };
 
     if !data_dir.exists() {
@@ -61,47 +109,87 @@ fn inner_main() -> Result<()> {
     let socket_path = data_dir.join("bjobs.sock");
     let log_file = data_dir.join("daemon.log");
 
+    let connect = || {
+        if config.autostart.value {
+            DaemonClient::connect_or_start(&socket_path, &data_dir)
+        } else {
+            DaemonClient::connect(&socket_path)
+        }
+    };
+
     match cmd.action {
-        Action::List => {
-            let mut client = DaemonClient::connect(&socket_path)?;
+        Action::List(ListArgs {
+            statuses,
+            names,
+            tags,
+            sort,
+            columns,
+            watch,
+            interval,
+        }) => {
+            let client = connect()?;
 
-            let tasks = client.tasks()?;
+            let filter = TaskFilter {
+                names,
+                tags,
+                statuses,
+            };
 
-            if tasks.is_empty() {
-                info!("No task found.");
-                return Ok(());
+            loop {
+                let mut tasks = client.list(filter.clone())?;
+
+                match sort {
+                    SortBy::Name => tasks.sort_by(|a, b| a.task.name.cmp(&b.task.name)),
+                    // Tasks that didn't start yet come last
+                    SortBy::Started => {
+                        tasks.sort_by_key(|task| (task.started_at.is_none(), task.started_at))
+                    }
+                    SortBy::Duration => {
+                        tasks.sort_by_key(|task| std::cmp::Reverse(task.duration_ms))
+                    }
+                }
+
+                if watch {
+                    // Clear the screen and move the cursor to the top-left corner
+                    print!("\x1B[2J\x1B[H");
+                }
+
+                print_tasks_table(&tasks, &columns);
+
+                if !watch {
+                    break;
+                }
+
+                sleep_ms(interval * 1000);
             }
+        }
 
-            info!("Found {} task(s):", tasks.len().to_string().bright_yellow());
-            info!("");
+        Action::Top => {
+            run_dashboard(connect()?)?;
+        }
 
-            let mut table = Table::new("{:>} {:<} {:<} {:<} {:<}");
+        Action::Show(ShowArgs { name, lines, json }) => {
+            let client = connect()?;
 
-            for TaskWrapper { task, state } in tasks.values() {
-                table.add_row(row!(
-                    "*".bright_blue(),
-                    task.name.bright_yellow(),
-                    match &state.lock().unwrap().status {
-                        TaskStatus::NotStartedYet => "Not started yet".bright_black(),
-                        TaskStatus::Running { child: _ } => "Running".bright_cyan(),
-                        TaskStatus::Success => "Succeeded".bright_green(),
-                        TaskStatus::Failed { code: _ } => "Failed".bright_red(),
-                        TaskStatus::RunnerFailed { message } =>
-                            format!("Runner failed ({message})").bright_red(),
-                    },
-                    match &task.shell {
-                        Some(shell) => shell.bright_magenta(),
-                        None => "-".bright_black(),
-                    },
-                    task.cmd.bright_magenta(),
-                ));
+            let details = client
+                .task_details(name, lines)?
+                .map_err(|err| anyhow!("{err}"))?;
+
+            if json {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&details)
+                        .context("Failed to serialize task details")?
+                );
+
+                return Ok(0);
             }
 
-            println!("{}", table);
+            print_task_details(&details);
         }
 
         Action::Start(args) => {
-            start_daemon(&socket_path, &log_file, &args)?;
+            start_daemon(&socket_path, &log_file, &data_dir, &args, config.clone())?;
         }
 
         Action::Run(RunArgs {
@@ -109,6 +197,16 @@ fn inner_main() -> Result<()> {
             using: shell,
             cmd: task_cmd,
             start_dir,
+            env,
+            secret_env,
+            tags,
+            restart,
+            depends_on,
+            keep_running,
+            on_start,
+            on_success,
+            on_failure,
+            hook_timeout,
             silent,
             ignore_identicals,
             restart_if_finished,
@@ -118,20 +216,38 @@ fn inner_main() -> Result<()> {
                 cmd: task_cmd,
                 shell,
                 start_dir,
+                env: env.into_iter().collect(),
+                secret_env: secret_env
+                    .iter()
+                    .map(|secret| secret.name.clone())
+                    .collect(),
+                tags,
+                restart,
+                depends_on,
+                keep_running,
+                on_start,
+                on_success,
+                on_failure,
+                hook_timeout,
             };
 
-            let mut client = DaemonClient::connect(&socket_path)?;
+            let secrets = read_secrets(&secret_env)?;
+
+            let client = connect()?;
 
-            let tasks = client.tasks()?;
+            // Summaries don't include the tasks' output
+            let existing = client
+                .list(TaskFilter::default())?
+                .into_iter()
+                .find(|summary| summary.task.name == name);
 
-            if let Some(TaskWrapper {
+            if let Some(TaskSummary {
                 task: existing,
-                state,
-            }) = tasks.get(&name)
+                status,
+                ..
+            }) = existing
             {
-                if existing.shell == task.shell && existing.cmd == task.cmd && ignore_identicals {
-                    let status = { state.lock().unwrap().status.clone_without_child_id() };
-
+                if ignore_identicals && existing.diff(&task).is_empty() {
                     if restart_if_finished && status.is_completed() {
                         if status.is_failure() {
                             warn!("Restarting failed task {}.", name.bright_yellow());
@@ -139,24 +255,206 @@ fn inner_main() -> Result<()> {
                             success!("Restarting task {}.", name.bright_yellow());
                         }
 
-                        client.restart(task.name)?.map_err(|err| anyhow!("{err}"))?;
+                        // Provide the secrets again in case they were lost
+                        client
+                            .replace(task, secrets)?
+                            .map_err(|err| anyhow!("{err}"))?;
                     }
 
-                    return Ok(());
+                    return Ok(0);
                 }
 
                 bail!("A task with this name already exists!");
             }
 
-            client.run(task)?;
+            client.run(task, secrets)?.map_err(|err| anyhow!("{err}"))?;
 
             if !silent {
                 success!("Successfully registered task {}.", name.bright_yellow());
             }
         }
 
+        Action::Apply(ApplyArgs {
+            file,
+            prune,
+            dry_run,
+        }) => {
+            let declared = TaskFile::load(&file)?;
+
+            let client = connect()?;
+
+            let existing = client
+                .list(TaskFilter::default())?
+                .into_iter()
+                .map(|summary| (summary.task.name.clone(), summary))
+                .collect::<BTreeMap<_, _>>();
+
+            let undeclared = existing
+                .keys()
+                .filter(|name| !declared.iter().any(|task| &&task.name == name))
+                .cloned()
+                .collect::<Vec<_>>();
+
+            let mut changes = 0;
+
+            for task in declared {
+                match existing.get(&task.name) {
+                    None => {
+                        println!("{} {}", "+".bright_green(), task.name.bright_yellow());
+
+                        if !dry_run {
+                            client
+                                .run(task, BTreeMap::new())?
+                                .map_err(|err| anyhow!("{err}"))?;
+                        }
+                    }
+
+                    Some(summary) => {
+                        let diff = summary.task.diff(&task);
+
+                        if diff.is_empty() {
+                            println!("{} {}", "=".bright_black(), task.name.bright_black());
+                            continue;
+                        }
+
+                        println!(
+                            "{} {} {}",
+                            "~".bright_blue(),
+                            task.name.bright_yellow(),
+                            format!("(changed: {})", diff.join(", ")).bright_black()
+                        );
+
+                        if !dry_run {
+                            client
+                                .replace(task, BTreeMap::new())?
+                                .map_err(|err| anyhow!("{err}"))?;
+                        }
+                    }
+                }
+
+                changes += 1;
+            }
+
+            if prune {
+                for name in undeclared {
+                    println!("{} {}", "-".bright_red(), name.bright_yellow());
+
+                    if !dry_run {
+                        kill_and_remove(&client, name)?;
+                    }
+
+                    changes += 1;
+                }
+            }
+
+            if changes == 0 {
+                info!("Nothing to do.");
+            } else if dry_run {
+                info!("Dry run: {changes} change(s) would be applied.");
+            } else {
+                success!("Successfully applied {changes} change(s).");
+            }
+        }
+
+        Action::Export(ExportArgs {
+            with_status,
+            with_logs,
+        }) => {
+            let client = connect()?;
+
+            let export = Export::new(client.tasks()?, with_status, with_logs);
+
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&export).context("Failed to serialize the export")?
+            );
+        }
+
+        Action::Import(ImportArgs { file, on_conflict }) => {
+            let content = if file.to_str() == Some("-") {
+                let mut content = String::new();
+
+                io::stdin()
+                    .read_to_string(&mut content)
+                    .context("Failed to read the export from STDIN")?;
+
+                content
+            } else {
+                fs::read_to_string(&file).context("Failed to read the export file")?
+            };
+
+            let export =
+                serde_json::from_str::<Export>(&content).context("Failed to parse the export")?;
+
+            let client = connect()?;
+
+            let mut existing = client
+                .list(TaskFilter::default())?
+                .into_iter()
+                .map(|summary| summary.task.name)
+                .collect::<Vec<_>>();
+
+            for mut exported in export.tasks {
+                let name = exported.task.name.clone();
+
+                // The secrets' values are never exported
+                let secrets = if exported.task.secret_env.is_empty() {
+                    BTreeMap::new()
+                } else {
+                    info!("Task {} requires secrets.", name.bright_yellow());
+                    prompt_secrets(&exported.task.secret_env)?
+                };
+
+                if !existing.contains(&name) {
+                    client
+                        .run(exported.task, secrets)?
+                        .map_err(|err| anyhow!("{err}"))?;
+                    success!("Imported task {}.", name.bright_yellow());
+
+                    existing.push(name);
+                    continue;
+                }
+
+                match on_conflict {
+                    ConflictStrategy::Skip => {
+                        warn!(
+                            "Skipped task {} as it already exists.",
+                            name.bright_yellow()
+                        );
+                    }
+
+                    ConflictStrategy::Replace => {
+                        client
+                            .replace(exported.task, secrets)?
+                            .map_err(|err| anyhow!("{err}"))?;
+
+                        success!("Replaced task {}.", name.bright_yellow());
+                    }
+
+                    ConflictStrategy::Rename => {
+                        let new_name = available_name(&name, |candidate| {
+                            existing.iter().any(|name| name == candidate)
+                        });
+
+                        exported.task.name = new_name.clone();
+                        client
+                            .run(exported.task, secrets)?
+                            .map_err(|err| anyhow!("{err}"))?;
+
+                        success!(
+                            "Imported task {} as {}.",
+                            name.bright_yellow(),
+                            new_name.bright_yellow()
+                        );
+
+                        existing.push(new_name);
+                    }
+                }
+            }
+        }
+
         Action::Kill(KillArgs { name }) => {
-            let mut client = DaemonClient::connect(&socket_path)?;
+            let client = connect()?;
 
             client.kill(name)?.map_err(|err| anyhow!("{err}"))?;
 
@@ -164,7 +462,7 @@ fn inner_main() -> Result<()> {
         }
 
         Action::Restart(RestartArgs { name }) => {
-            let mut client = DaemonClient::connect(&socket_path)?;
+            let client = connect()?;
 
             client.restart(name)?.map_err(|err| anyhow!("{err}"))?;
 
@@ -172,61 +470,140 @@ fn inner_main() -> Result<()> {
         }
 
         Action::Remove(RemoveArgs { name }) => {
-            let mut client = DaemonClient::connect(&socket_path)?;
+            let client = connect()?;
 
             client.remove(name)?.map_err(|err| anyhow!("{err}"))?;
 
             success!("Successfully removed task.");
         }
 
-        Action::Check(CheckArgs { succeeded, silent }) => {
-            let mut client = DaemonClient::connect(&socket_path)?;
+        Action::Wait(WaitArgs {
+            names,
+            timeout,
+            failed_code,
+            running_code,
+        }) => {
+            let client = connect()?;
+
+            let statuses = client
+                .wait(names, timeout)?
+                .map_err(|err| anyhow!("{err}"))?;
+
+            for (name, status) in &statuses {
+                info!("Task {}: {}", name.bright_yellow(), format_status(status));
+            }
+
+            return Ok(if statuses.values().any(TaskStatus::is_failure) {
+                failed_code
+            } else if statuses.values().all(TaskStatus::is_completed) {
+                0
+            } else {
+                running_code
+            });
+        }
+
+        Action::Check(CheckArgs {
+            names,
+            tags,
+            succeeded,
+            silent,
+            failed_code,
+            running_code,
+            ack,
+            clear,
+        }) => {
+            let client = connect()?;
 
-            let tasks = client.tasks()?;
+            let tasks = client.list(TaskFilter {
+                names,
+                tags,
+                statuses: vec![],
+            })?;
 
             if tasks.is_empty() {
-                info!("No task found.");
-                return Ok(());
+                if !silent {
+                    info!("No task found.");
+                }
+
+                return Ok(0);
             }
 
-            let mut failed = None;
+            let mut failed = vec![];
+            let mut running = 0;
 
-            for (name, task) in tasks {
-                match &task.state.lock().unwrap().status {
-                    TaskStatus::NotStartedYet | TaskStatus::Running { child: _ } => {}
+            for TaskSummary {
+                task,
+                status,
+                acknowledged,
+                ..
+            } in tasks
+            {
+                let exit_msg = match &status {
+                    TaskStatus::NotStartedYet
+                    | TaskStatus::Running { child: _ }
+                    | TaskStatus::Orphaned => {
+                        running += 1;
+                        continue;
+                    }
+
+                    // The exit status of adopted tasks is unknown
+                    TaskStatus::Exited => continue,
 
                     TaskStatus::Success => {
-                        if succeeded {
-                            failed = Some((name, "gracefully".bright_green()));
+                        if succeeded && !silent {
+                            success!(
+                                "Task {} exited ({})",
+                                task.name.bright_yellow(),
+                                "gracefully".bright_green()
+                            );
                         }
-                    }
 
-                    TaskStatus::Failed { code } => {
-                        failed = Some((
-                            name,
-                            match code {
-                                None => "failed - no exit code".bright_yellow(),
-                                Some(code) => {
-                                    format!("failed with exit code {code}").bright_yellow()
-                                }
-                            },
-                        ));
+                        continue;
                     }
 
+                    _ if acknowledged => continue,
+
+                    TaskStatus::Failed { code, signal } => match (code, signal) {
+                        (Some(code), _) => format!("failed with exit code {code}"),
+                        (None, Some(signal)) => format!("killed by signal {signal}"),
+                        (None, None) => "failed - no exit code".to_owned(),
+                    },
+
                     TaskStatus::RunnerFailed { message } => {
-                        failed = Some((
-                            name,
-                            format!("task runner failed with message '{message}'").bright_yellow(),
-                        ))
+                        format!("task runner failed with message '{message}'")
                     }
+                };
+
+                if !silent {
+                    error!(
+                        "Task {} exited ({})",
+                        task.name.bright_yellow(),
+                        exit_msg.bright_yellow()
+                    );
                 }
+
+                failed.push(task.name);
             }
 
-            if !silent {
-                if let Some((task_name, exit_msg)) = failed {
-                    bail!("Task {} exited ({})", task_name.bright_yellow(), exit_msg);
+            if failed.is_empty() {
+                return Ok(if running > 0 { running_code } else { 0 });
+            }
+
+            if ack {
+                client
+                    .acknowledge(failed.clone())?
+                    .map_err(|err| anyhow!("{err}"))?;
+            }
+
+            if clear {
+                for name in &failed {
+                    client
+                        .remove(name.clone())?
+                        .map_err(|err| anyhow!("{err}"))?;
                 }
             }
+
+            return Ok(failed_code);
         }
 
         Action::Status => {
@@ -234,29 +611,91 @@ fn inner_main() -> Result<()> {
 
             if !is_daemon_running(&socket_path)? {
                 warn!("Daemon is not running.");
-                return Ok(());
+                return Ok(0);
             }
 
             debug!("Daemon is running, sending a test request...");
 
-            let mut client = DaemonClient::connect(&socket_path)?;
+            let client = DaemonClient::connect(&socket_path)?;
             let pid = client.hello()?;
 
             success!("Daemon is running and responding to requests.");
             debug!("Daemon PID: {pid}");
+
+            if client.server_version() != env!("CARGO_PKG_VERSION") {
+                warn!(
+                    "Daemon is running version {} while this client is version {}, it must be restarted to use the new version.",
+                    client.server_version(),
+                    env!("CARGO_PKG_VERSION")
+                );
+            }
         }
 
-        Action::Stop => {
+        Action::Profiles => {
+            let mut table = Table::new("{:>} {:<} {:<} {:<}");
+
+            let profiles = std::iter::once(None)
+                .chain(list_profiles(&config.data_dir.value)?.into_iter().map(Some))
+                .collect::<Vec<_>>();
+
+            for name in &profiles {
+                let data_dir = match name {
+                    Some(name) => profile_data_dir(&config.data_dir.value, name)?,
+                    None => config.data_dir.value.clone(),
+                };
+
+                let socket_path = data_dir.join("bjobs.sock");
+
+                let status = if is_daemon_running(&socket_path)? {
+                    let client = DaemonClient::connect(&socket_path)?;
+
+                    let tasks = client.list(TaskFilter::default())?;
+                    let running = tasks
+                        .iter()
+                        .filter(|summary| !summary.status.is_completed())
+                        .count();
+
+                    format!("running ({} task(s), {running} running)", tasks.len()).bright_green()
+                } else {
+                    "stopped".bright_black()
+                };
+
+                table.add_row(row!(
+                    if *name == profile { "*" } else { " " }.bright_blue(),
+                    match name {
+                        Some(name) => name.bright_yellow(),
+                        None => "(default)".bright_yellow(),
+                    },
+                    status,
+                    data_dir.display().to_string().bright_magenta()
+                ));
+            }
+
+            info!(
+                "Found {} profile(s):",
+                profiles.len().to_string().bright_yellow()
+            );
+            info!("");
+
+            println!("{table}");
+        }
+
+        Action::Stop(args) => {
             debug!("Asking the daemon to stop...");
 
-            let mut client = DaemonClient::connect(&socket_path)?;
+            let wait = args.wait;
+
+            let client = DaemonClient::connect(&socket_path)?;
+
+            // The connection is used to detect when the daemon exited
+            client.disable_reconnect();
 
-            match client.stop() {
+            match client.stop(args.wait, args.timeout, args.force) {
                 Ok(()) => {}
                 Err(err) => {
                     if let Ok(false) = is_daemon_running(&socket_path) {
                         success!("Daemon was successfully stopped!");
-                        return Ok(());
+                        return Ok(0);
                     }
 
                     return Err(err);
@@ -266,70 +705,252 @@ fn inner_main() -> Result<()> {
             debug!("Request succesfully transmitted, waiting for the daemon to actually stop...");
 
             let mut last_running = 0;
-            let mut had_error = false;
 
-            loop {
-                match is_daemon_running(&socket_path) {
-                    Ok(true) => {}
-                    Ok(false) => break,
-                    Err(err) => {
-                        if had_error {
-                            return Err(err);
-                        }
+            // The connection is closed once the daemon exited, which happens after it removed
+            // the socket file (when the socket is managed by systemd, it stays in place and
+            // a new connection would start a new daemon)
+            while let Ok(running) = client.running_tasks_count() {
+                if wait && running != last_running {
+                    info!("Waiting for {} task(s) to complete...", running);
+                    last_running = running;
+                }
 
-                        had_error = true;
-                        sleep_ms(20);
-                        continue;
-                    }
+                sleep_ms(100);
+            }
+
+            success!("Daemon was successfully stopped!");
+        }
+
+        Action::Daemon(DaemonAction::Restart) => {
+            let client = DaemonClient::connect(&socket_path)?;
+
+            let pid = client.hello()?;
+            debug!("Asking the daemon (PID {pid}) to hand off to a new process...");
+
+            client.disable_reconnect();
+
+            client.handoff()?.map_err(|err| anyhow!("{err}"))?;
+
+            // The connection is closed when the daemon's process is replaced
+            while client.hello().is_ok() {
+                sleep_ms(20);
+            }
+
+            let started = Instant::now();
+
+            while DaemonClient::connect(&socket_path)
+                .and_then(|client| client.hello())
+                .is_err()
+            {
+                if started.elapsed() > Duration::from_secs(10) {
+                    bail!(
+                        "The new daemon process did not start in time, see its logs at: {}",
+                        log_file.display()
+                    );
                 }
 
-                let running = match client.running_tasks_count() {
-                    Ok(running) => running,
-                    Err(err) => {
-                        if had_error {
-                            return Err(err);
-                        }
+                sleep_ms(50);
+            }
 
-                        had_error = true;
-                        sleep_ms(20);
-                        continue;
-                    }
-                };
+            success!("Daemon was successfully restarted!");
+        }
 
-                if running != last_running {
-                    info!("Waiting for {} task(s) to complete...", running);
-                    last_running = running;
+        Action::Daemon(DaemonAction::Reload) => {
+            let client = DaemonClient::connect(&socket_path)?;
+
+            let ReloadReport {
+                applied,
+                requires_restart,
+            } = client.reload()?.map_err(|err| anyhow!("{err}"))?;
+
+            if applied.is_empty() && requires_restart.is_empty() {
+                info!("Configuration was reloaded, no change to apply.");
+            }
+
+            if !applied.is_empty() {
+                success!(
+                    "Applied new values for: {}",
+                    applied.join(", ").bright_yellow()
+                );
+            }
+
+            if !requires_restart.is_empty() {
+                warn!(
+                    "The daemon must be restarted to use the new values for: {}",
+                    requires_restart.join(", ").bright_yellow()
+                );
+            }
+        }
+
+        Action::Daemon(DaemonAction::InstallUnit(InstallUnitArgs { print, force })) => {
+            let units = generate_units(&data_dir, profile.as_deref())?;
+
+            if print {
+                for unit in &units {
+                    println!("# {}\n{}", unit.file_name, unit.content);
                 }
 
-                sleep_ms(100);
+                return Ok(0);
             }
 
-            // Just to ensure process completely exited
-            while socket_path.exists() {
-                sleep_ms(100);
+            let units_dir = user_units_dir()?;
+
+            if !units_dir.exists() {
+                fs::create_dir_all(&units_dir)
+                    .context("Failed to create the systemd user units directory")?;
             }
 
-            success!("Daemon was successfully stopped!");
+            for unit in &units {
+                let path = units_dir.join(&unit.file_name);
+
+                if path.exists() && !force {
+                    bail!(
+                        "Unit file already exists at '{}' (use --force to overwrite it)",
+                        path.display()
+                    );
+                }
+            }
+
+            for unit in &units {
+                let path = units_dir.join(&unit.file_name);
+
+                fs::write(&path, &unit.content).with_context(|| {
+                    format!("Failed to write unit file at '{}'", path.display())
+                })?;
+
+                success!("Written unit file at '{}'", path.display());
+            }
+
+            if is_daemon_running(&socket_path)? {
+                warn!("The daemon is currently running, it must be stopped before enabling the units.");
+            }
+
+            info!("To start the daemon on demand from now on, run:");
+            println!("    systemctl --user daemon-reload");
+            println!(
+                "    systemctl --user enable --now {}.socket",
+                unit_name(profile.as_deref())
+            );
+        }
+
+        Action::Audit(AuditArgs {
+            task,
+            call,
+            user,
+            last,
+            json,
+            verify,
+        }) => {
+            // Get the daemon's last hash first, as entries may be added while reading the log
+            let last_hash = if verify && is_daemon_running(&socket_path)? {
+                Some(connect()?.audit_last_hash()?)
+            } else {
+                None
+            };
+
+            let AuditLogContent {
+                entries,
+                invalid_lines,
+            } = read_audit_log(&audit_file(&data_dir))?;
+
+            if !invalid_lines.is_empty() {
+                warn!(
+                    "Skipped {} invalid line(s) in the audit log.",
+                    invalid_lines.len()
+                );
+            }
+
+            if verify {
+                if last_hash.is_none() {
+                    warn!("The daemon is not running, entries removed from the end of the log can't be detected.");
+                }
+
+                let key = read_audit_key(&data_dir)?;
+
+                match verify_audit_log(&entries, &key, last_hash.as_deref()) {
+                    Some(index) if index == entries.len() => {
+                        error!("Audit log was tampered with: entries were removed from its end.");
+                        return Ok(1);
+                    }
+                    Some(index) => {
+                        error!(
+                            "Audit log was tampered with: entry {} doesn't match the previous ones.",
+                            index + 1
+                        );
+                        return Ok(1);
+                    }
+                    None if !invalid_lines.is_empty() => {
+                        error!("Audit log has entries that can't be parsed.");
+                        return Ok(1);
+                    }
+                    None => success!("Audit log is valid ({} entries).", entries.len()),
+                }
+
+                return Ok(0);
+            }
+
+            let entries = entries
+                .iter()
+                .filter(|entry| task.as_ref().is_none_or(|task| entry.mentions_task(task)))
+                .filter(|entry| call.as_ref().is_none_or(|call| &entry.record.call == call))
+                .filter(|entry| {
+                    user.as_ref().is_none_or(|user| {
+                        entry.record.caller.user.as_ref() == Some(user)
+                            || entry.record.caller.uid.map(|uid| uid.to_string()).as_ref()
+                                == Some(user)
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            let skip = last.map_or(0, |last| entries.len().saturating_sub(last));
+
+            if entries.is_empty() && !json {
+                info!("No call found.");
+            }
+
+            for entry in &entries[skip..] {
+                if json {
+                    println!("{}", serde_json::to_string(entry)?);
+                } else {
+                    print_audit_entry(entry);
+                }
+            }
+        }
+
+        Action::Config(ConfigAction::Show) => {
+            print_config(&config);
         }
 
         Action::Logs(LogsArgs {
             task_name,
             follow,
+            hooks,
             pager,
             no_less_options,
         }) => {
-            let pager = pager
-                .or_else(|| std::env::var("PAGER").ok())
-                .unwrap_or_else(|| "less".to_owned());
+            let pager = pager.unwrap_or_else(|| config.pager.value.clone());
 
             run_pager(
                 || match &task_name {
+                    // Hooks are run by the daemon, which writes their output to files only
+                    Some(task_name) if hooks => {
+                        let hook_log_file = hook_log_file(&data_dir.join("logs"), task_name);
+
+                        if !hook_log_file.exists() {
+                            return Ok(String::new());
+                        }
+
+                        fs::read_to_string(&hook_log_file)
+                            .context("Failed to read the hooks' log file")
+                    }
+
                     Some(task_name) => {
-                        let mut client = DaemonClient::connect(&socket_path)?;
+                        let client = connect()?;
 
                         Ok(client
-                            .logs(task_name.clone())?
+                            .logs(task_name.clone(), 0)?
                             .map_err(|err| anyhow!("{err}"))?
+                            .1
                             .join("\n"))
                     }
 
@@ -342,5 +963,364 @@ fn inner_main() -> Result<()> {
         }
     }
 
-    Ok(())
+    Ok(0)
+}
+
+fn kill_and_remove(client: &DaemonClient, name: String) -> Result<()> {
+    let status = |client: &DaemonClient| -> Result<TaskStatus> {
+        let task = client.task(name.clone())?.map_err(|err| anyhow!("{err}"))?;
+        let status = task.state.lock().unwrap().status.clone_without_child_id();
+        Ok(status)
+    };
+
+    if !status(client)?.is_completed() {
+        client.kill(name.clone())?.map_err(|err| anyhow!("{err}"))?;
+
+        while !status(client)?.is_completed() {
+            sleep_ms(50);
+        }
+    }
+
+    client.remove(name)?.map_err(|err| anyhow!("{err}"))
+}
+
+fn format_status(status: &TaskStatus) -> ColoredString {
+    match status {
+        TaskStatus::NotStartedYet => "Not started yet".bright_black(),
+        TaskStatus::Running { child: _ } => "Running".bright_cyan(),
+        TaskStatus::Success => "Succeeded".bright_green(),
+        TaskStatus::Failed { .. } => "Failed".bright_red(),
+        TaskStatus::RunnerFailed { message } => format!("Runner failed ({message})").bright_red(),
+        TaskStatus::Orphaned => "Orphaned".bright_red(),
+        TaskStatus::Exited => "Exited (unknown status)".bright_black(),
+    }
+}
+
+fn print_tasks_table(tasks: &[TaskSummary], columns: &[Column]) {
+    if tasks.is_empty() {
+        info!("No task found.");
+        return;
+    }
+
+    info!("Found {} task(s):", tasks.len().to_string().bright_yellow());
+    info!("");
+
+    let none = || "-".bright_black();
+
+    let mut table = Table::new(&format!("{{:>}}{}", " {:<}".repeat(columns.len())));
+
+    for summary in tasks {
+        let mut row = Row::new().with_cell("*".bright_blue());
+
+        for column in columns {
+            let task = &summary.task;
+
+            row.add_cell(match column {
+                Column::Name => task.name.bright_yellow(),
+                Column::Status => format_status(&summary.status),
+                Column::Shell => match &task.shell {
+                    Some(shell) => shell.bright_magenta(),
+                    None => none(),
+                },
+                Column::Cmd => task.cmd.bright_magenta(),
+                Column::Tags => {
+                    if task.tags.is_empty() {
+                        none()
+                    } else {
+                        task.tags.join(",").bright_magenta()
+                    }
+                }
+                Column::Pid => match summary.pid {
+                    Some(pid) => pid.to_string().bright_yellow(),
+                    None => none(),
+                },
+                Column::Started => match summary.started_at {
+                    Some(started_at) => {
+                        format_timestamp(second_precision(started_at)).bright_yellow()
+                    }
+                    None => none(),
+                },
+                Column::Duration => match summary.duration_ms {
+                    Some(duration) => format_duration_ms(duration).bright_yellow(),
+                    None => none(),
+                },
+                Column::Restarts => summary.restarts.to_string().bright_yellow(),
+            });
+        }
+
+        table.add_row(row);
+    }
+
+    println!("{}", table);
+}
+
+fn print_audit_entry(AuditEntry { record, .. }: &AuditEntry) {
+    let caller = match (&record.caller.user, record.caller.uid) {
+        (Some(user), _) => user.clone(),
+        (None, Some(uid)) => format!("UID {uid}"),
+        (None, None) => "unknown user".to_owned(),
+    };
+
+    let pid = record
+        .caller
+        .pid
+        .map(|pid| format!(" (PID {pid})"))
+        .unwrap_or_default();
+
+    let args = match &record.args {
+        serde_json::Value::Object(args) => args
+            .iter()
+            .map(|(name, value)| format!(" {name}={value}"))
+            .collect::<String>(),
+        _ => String::new(),
+    };
+
+    let outcome = match &record.error {
+        Some(err) => format!(" -> {}", format!("failed: {err}").bright_red()),
+        None => String::new(),
+    };
+
+    println!(
+        "[{}] {}{} via {}: {}{}{}",
+        format_timestamp(record.at).bright_magenta(),
+        caller.bright_yellow(),
+        pid,
+        record.caller.via,
+        record.call.bright_cyan(),
+        args,
+        outcome
+    );
+}
+
+fn print_config(config: &Config) {
+    info!(
+        "Configuration file: {}{}",
+        config.path.display().to_string().bright_magenta(),
+        if config.path_exists {
+            ""
+        } else {
+            " (not found)"
+        }
+    );
+    info!("");
+
+    let unlimited = || "unlimited".to_owned();
+
+    let mut table = Table::new("{:<} {:<} {:<}");
+
+    table.add_row(config_row("data_dir", &config.data_dir, |dir| {
+        dir.display().to_string()
+    }));
+    table.add_row(config_row(
+        "default_shell",
+        &config.default_shell,
+        String::clone,
+    ));
+    table.add_row(config_row(
+        "log_retention",
+        &config.log_retention,
+        |lines| lines.map_or_else(unlimited, |lines| format!("{lines} lines")),
+    ));
+    table.add_row(config_row(
+        "max_concurrent",
+        &config.max_concurrent,
+        |max| max.map_or_else(unlimited, |max| max.to_string()),
+    ));
+    table.add_row(config_row("autostart", &config.autostart, bool::to_string));
+    table.add_row(config_row(
+        "timestamp_format",
+        &config.timestamp_format,
+        |format| format.clone().unwrap_or_else(|| "(default)".to_owned()),
+    ));
+    table.add_row(config_row("pager", &config.pager, String::clone));
+    table.add_row(config_row(
+        "connect_timeout",
+        &config.connect_timeout,
+        |secs| format!("{secs}s"),
+    ));
+    table.add_row(config_row(
+        "request_timeout",
+        &config.request_timeout,
+        |secs| secs.map_or_else(|| "none".to_owned(), |secs| format!("{secs}s")),
+    ));
+    table.add_row(config_row("http_listen", &config.http_listen, |addr| {
+        addr.clone().unwrap_or_else(|| "disabled".to_owned())
+    }));
+    table.add_row(config_row(
+        "http_allow_origin",
+        &config.http_allow_origin,
+        |origin| origin.clone().unwrap_or_else(|| "none".to_owned()),
+    ));
+    table.add_row(config_row("full_access", &config.full_access, |list| {
+        access_list(list)
+    }));
+    table.add_row(config_row(
+        "read_only_access",
+        &config.read_only_access,
+        |list| access_list(list),
+    ));
+    table.add_row(config_row("on_start", &config.on_start, hook_command));
+    table.add_row(config_row("on_success", &config.on_success, hook_command));
+    table.add_row(config_row("on_failure", &config.on_failure, hook_command));
+    table.add_row(config_row("hook_timeout", &config.hook_timeout, |secs| {
+        secs.map_or_else(|| "none".to_owned(), |secs| format!("{secs}s"))
+    }));
+
+    println!("{table}");
+}
+
+fn access_list(list: &[String]) -> String {
+    if list.is_empty() {
+        "none".to_owned()
+    } else {
+        list.join(", ")
+    }
+}
+
+fn hook_command(cmd: &Option<String>) -> String {
+    cmd.clone().unwrap_or_else(|| "none".to_owned())
+}
+
+fn config_row<T>(name: &str, setting: &Setting<T>, display: impl Fn(&T) -> String) -> Row {
+    row!(
+        name.bright_blue(),
+        display(&setting.value).bright_yellow(),
+        format!("({})", setting.source).bright_black()
+    )
+}
+
+fn print_task_details(details: &TaskDetails) {
+    let none = || "-".bright_black();
+
+    let mut table = Table::new("{:<} {:<}");
+
+    table.add_row(row!("Name".bright_blue(), details.name.bright_yellow()));
+    table.add_row(row!("Status".bright_blue(), format_status(&details.status)));
+    table.add_row(row!("Command".bright_blue(), details.cmd.bright_magenta()));
+    table.add_row(row!(
+        "Shell".bright_blue(),
+        match &details.shell {
+            Some(shell) => shell.bright_magenta(),
+            None => "(default)".bright_black(),
+        }
+    ));
+    table.add_row(row!(
+        "Working directory".bright_blue(),
+        match &details.start_dir {
+            Some(start_dir) => start_dir.display().to_string().bright_magenta(),
+            None => "(daemon's)".bright_black(),
+        }
+    ));
+    table.add_row(row!(
+        "Environment".bright_blue(),
+        if details.env.is_empty() {
+            none()
+        } else {
+            details
+                .env
+                .iter()
+                .map(|(key, value)| format!("{key}={value}"))
+                .collect::<Vec<_>>()
+                .join(" ")
+                .bright_magenta()
+        }
+    ));
+    table.add_row(row!(
+        "Secret environment".bright_blue(),
+        if details.secret_env.is_empty() {
+            none()
+        } else {
+            details.secret_env.join(", ").bright_magenta()
+        }
+    ));
+
+    for (name, hook) in [
+        ("On start", &details.on_start),
+        ("On success", &details.on_success),
+        ("On failure", &details.on_failure),
+    ] {
+        table.add_row(row!(
+            name.bright_blue(),
+            match hook {
+                Some(cmd) => cmd.bright_magenta(),
+                None => none(),
+            }
+        ));
+    }
+
+    table.add_row(row!(
+        "Hook timeout".bright_blue(),
+        match details.hook_timeout {
+            Some(0) => "none".bright_magenta(),
+            Some(secs) => format!("{secs}s").bright_magenta(),
+            None => "daemon's setting".bright_black(),
+        }
+    ));
+    table.add_row(row!(
+        "Tags".bright_blue(),
+        if details.tags.is_empty() {
+            none()
+        } else {
+            details.tags.join(", ").bright_magenta()
+        }
+    ));
+    table.add_row(row!(
+        "PID".bright_blue(),
+        match (details.pid, details.pgid) {
+            (Some(pid), Some(pgid)) => format!("{pid} (process group {pgid})").bright_yellow(),
+            (Some(pid), None) => pid.to_string().bright_yellow(),
+            (None, _) => none(),
+        }
+    ));
+    table.add_row(row!(
+        "Started at".bright_blue(),
+        match details.started_at {
+            Some(started_at) => format_timestamp(started_at).bright_yellow(),
+            None => none(),
+        }
+    ));
+    table.add_row(row!(
+        "Ended at".bright_blue(),
+        match details.ended_at {
+            Some(ended_at) => format_timestamp(ended_at).bright_yellow(),
+            None => none(),
+        }
+    ));
+    table.add_row(row!(
+        "Duration".bright_blue(),
+        match details.duration_ms {
+            Some(duration) => format_duration_ms(duration).bright_yellow(),
+            None => none(),
+        }
+    ));
+    table.add_row(row!(
+        "Exit code".bright_blue(),
+        match (details.exit_code, details.signal) {
+            (Some(code), _) => code.to_string().bright_yellow(),
+            (None, Some(signal)) => format!("killed by signal {signal}").bright_yellow(),
+            (None, None) => none(),
+        }
+    ));
+    table.add_row(row!(
+        "Restarts".bright_blue(),
+        details.restarts.to_string().bright_yellow()
+    ));
+    table.add_row(row!(
+        "Log size".bright_blue(),
+        format!(
+            "{} line(s), {} byte(s)",
+            details.log_lines, details.log_bytes
+        )
+        .bright_yellow()
+    ));
+
+    println!("{}", table);
+
+    if !details.last_lines.is_empty() {
+        info!("Last {} line(s):", details.last_lines.len());
+
+        for line in &details.last_lines {
+            println!("{line}");
+        }
+    }
 }
@@ -0,0 +1,50 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+
+/// Get the data directory of a profile, which is isolated from the others
+pub fn profile_data_dir(base_data_dir: &Path, name: &str) -> Result<PathBuf> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        bail!(
+            "Invalid profile name '{name}', only alphanumeric characters, '-' and '_' are allowed"
+        );
+    }
+
+    Ok(profiles_dir(base_data_dir).join(name))
+}
+
+/// List the profiles which were used at least once
+pub fn list_profiles(base_data_dir: &Path) -> Result<Vec<String>> {
+    let profiles_dir = profiles_dir(base_data_dir);
+
+    if !profiles_dir.exists() {
+        return Ok(vec![]);
+    }
+
+    let mut profiles = vec![];
+
+    for entry in fs::read_dir(&profiles_dir).context("Failed to read the profiles directory")? {
+        let entry = entry.context("Failed to read the profiles directory")?;
+
+        if entry.path().is_dir() {
+            if let Some(name) = entry.file_name().to_str() {
+                profiles.push(name.to_owned());
+            }
+        }
+    }
+
+    profiles.sort();
+
+    Ok(profiles)
+}
+
+fn profiles_dir(base_data_dir: &Path) -> PathBuf {
+    base_data_dir.join("profiles")
+}
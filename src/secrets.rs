@@ -0,0 +1,143 @@
+use std::{collections::BTreeMap, fs, path::PathBuf};
+
+use anyhow::{Context, Result};
+
+/// Replacement for the secrets' values in the tasks' definition and output
+pub static REDACTED: &str = "[redacted]";
+
+/// Secret environment variable provided on the command line
+#[derive(Clone)]
+pub struct SecretEnv {
+    pub name: String,
+    /// File to read the value from, instead of prompting for it
+    pub file: Option<PathBuf>,
+}
+
+impl SecretEnv {
+    pub fn read(&self) -> Result<String> {
+        match &self.file {
+            Some(file) => {
+                let value = fs::read_to_string(file).with_context(|| {
+                    format!(
+                        "Failed to read secret '{}' from file '{}'",
+                        self.name,
+                        file.display()
+                    )
+                })?;
+
+                // Files usually end with a newline which isn't part of the secret
+                Ok(value
+                    .strip_suffix('\n')
+                    .map(|value| value.strip_suffix('\r').unwrap_or(value))
+                    .unwrap_or(&value)
+                    .to_owned())
+            }
+
+            None => prompt_secret(&self.name),
+        }
+    }
+}
+
+/// Parse a secret environment variable, as `NAME` to prompt for its value or `NAME=FILE` to read it from a file
+pub fn parse_secret_env(input: &str) -> Result<SecretEnv, String> {
+    let (name, file) = match input.split_once('=') {
+        Some((name, file)) => (name, Some(PathBuf::from(file))),
+        None => (input, None),
+    };
+
+    if name.is_empty() {
+        return Err(
+            "Expected a secret environment variable in NAME or NAME=FILE format".to_owned(),
+        );
+    }
+
+    Ok(SecretEnv {
+        name: name.to_owned(),
+        file,
+    })
+}
+
+/// Read the value of secret environment variables
+pub fn read_secrets(secrets: &[SecretEnv]) -> Result<BTreeMap<String, String>> {
+    secrets
+        .iter()
+        .map(|secret| Ok((secret.name.clone(), secret.read()?)))
+        .collect()
+}
+
+/// Prompt for the value of secret environment variables, e.g. for tasks being imported
+pub fn prompt_secrets(names: &[String]) -> Result<BTreeMap<String, String>> {
+    names
+        .iter()
+        .map(|name| Ok((name.clone(), prompt_secret(name)?)))
+        .collect()
+}
+
+fn prompt_secret(name: &str) -> Result<String> {
+    rpassword::prompt_password(format!("Value of secret {name}: ")).with_context(|| {
+        format!(
+            "Failed to prompt for secret '{name}', use NAME=FILE to read it from a file instead"
+        )
+    })
+}
+
+/// Replace the secrets' values in a text
+pub fn redact(text: &str, secrets: &BTreeMap<String, String>) -> String {
+    let mut values = secrets
+        .values()
+        .filter(|value| !value.is_empty())
+        .collect::<Vec<_>>();
+
+    // Replace the longest values first in case a secret contains another one
+    values.sort_by_key(|value| std::cmp::Reverse(value.len()));
+
+    values.into_iter().fold(text.to_owned(), |text, value| {
+        text.replace(value.as_str(), REDACTED)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::{parse_secret_env, redact};
+
+    fn secrets(values: &[(&str, &str)]) -> BTreeMap<String, String> {
+        values
+            .iter()
+            .map(|(name, value)| ((*name).to_owned(), (*value).to_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn redact_values() {
+        let secrets = secrets(&[("TOKEN", "abc123"), ("PASSWORD", "hunter2")]);
+
+        assert_eq!(
+            redact("token=abc123 password=hunter2 again=abc123", &secrets),
+            "token=[redacted] password=[redacted] again=[redacted]"
+        );
+        assert_eq!(redact("nothing secret", &secrets), "nothing secret");
+        assert_eq!(redact("abc123", &BTreeMap::new()), "abc123");
+    }
+
+    #[test]
+    fn redact_overlapping_and_empty_values() {
+        let secrets = secrets(&[("SHORT", "pass"), ("LONG", "password"), ("EMPTY", "")]);
+
+        assert_eq!(redact("password pass", &secrets), "[redacted] [redacted]");
+    }
+
+    #[test]
+    fn parse_secret_envs() {
+        let secret = parse_secret_env("TOKEN").unwrap();
+        assert_eq!(secret.name, "TOKEN");
+        assert!(secret.file.is_none());
+
+        let secret = parse_secret_env("TOKEN=/run/token").unwrap();
+        assert_eq!(secret.name, "TOKEN");
+        assert_eq!(secret.file.unwrap().to_str(), Some("/run/token"));
+
+        assert!(parse_secret_env("=/run/token").is_err());
+    }
+}
@@ -1,11 +1,130 @@
-use std::path::PathBuf;
+use std::{collections::BTreeMap, path::PathBuf};
 
+use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
 
+use crate::secrets::redact;
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Task {
     pub name: String,
     pub shell: Option<String>,
     pub cmd: String,
     pub start_dir: Option<PathBuf>,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub restart: RestartPolicy,
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+    /// Detach the task instead of killing it when the daemon stops
+    #[serde(default)]
+    pub keep_running: bool,
+    /// Name of the environment variables whose value is secret, which are provided separately
+    #[serde(default)]
+    pub secret_env: Vec<String>,
+    /// Commands run by the daemon when the task starts, succeeds or fails
+    pub on_start: Option<String>,
+    pub on_success: Option<String>,
+    pub on_failure: Option<String>,
+    /// Seconds after which the task's hooks are killed, overriding the daemon's setting (0 disables it)
+    pub hook_timeout: Option<u64>,
+}
+
+impl Task {
+    /// Get a copy of the task with the secrets' values replaced, to be shown to the user
+    pub fn redacted(&self, secrets: &BTreeMap<String, String>) -> Task {
+        Task {
+            cmd: redact(&self.cmd, secrets),
+            env: self
+                .env
+                .iter()
+                .map(|(name, value)| (name.clone(), redact(value, secrets)))
+                .collect(),
+            ..self.clone()
+        }
+    }
+
+    /// Get the name of the fields that differ between two task definitions
+    pub fn diff(&self, other: &Task) -> Vec<&'static str> {
+        let mut changes = vec![];
+
+        if self.shell != other.shell {
+            changes.push("shell");
+        }
+
+        if self.cmd != other.cmd {
+            changes.push("cmd");
+        }
+
+        if self.start_dir != other.start_dir {
+            changes.push("start_dir");
+        }
+
+        if self.env != other.env {
+            changes.push("env");
+        }
+
+        if self.tags != other.tags {
+            changes.push("tags");
+        }
+
+        if self.restart != other.restart {
+            changes.push("restart");
+        }
+
+        if self.depends_on != other.depends_on {
+            changes.push("depends_on");
+        }
+
+        if self.keep_running != other.keep_running {
+            changes.push("keep_running");
+        }
+
+        if self.secret_env != other.secret_env {
+            changes.push("secret_env");
+        }
+
+        if self.on_start != other.on_start {
+            changes.push("on_start");
+        }
+
+        if self.on_success != other.on_success {
+            changes.push("on_success");
+        }
+
+        if self.on_failure != other.on_failure {
+            changes.push("on_failure");
+        }
+
+        if self.hook_timeout != other.hook_timeout {
+            changes.push("hook_timeout");
+        }
+
+        changes
+    }
+}
+
+#[derive(Default, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    /// Never restart the task automatically
+    #[default]
+    Never,
+    /// Restart the task when it fails
+    OnFailure,
+    /// Restart the task whenever it exits
+    Always,
+}
+
+impl RestartPolicy {
+    pub fn should_restart(&self, failed: bool) -> bool {
+        match self {
+            RestartPolicy::Never => false,
+            RestartPolicy::OnFailure => failed,
+            RestartPolicy::Always => true,
+        }
+    }
 }
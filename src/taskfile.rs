@@ -0,0 +1,127 @@
+use std::{collections::BTreeMap, fs, path::Path, path::PathBuf};
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+
+use crate::task::{RestartPolicy, Task};
+
+/// Declarative description of a project's tasks
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TaskFile {
+    #[serde(default)]
+    pub tasks: BTreeMap<String, TaskDecl>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TaskDecl {
+    pub cmd: String,
+    pub shell: Option<String>,
+    /// Relative paths are resolved from the task file's directory
+    pub start_dir: Option<PathBuf>,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub restart: RestartPolicy,
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+    #[serde(default)]
+    pub keep_running: bool,
+    pub on_start: Option<String>,
+    pub on_success: Option<String>,
+    pub on_failure: Option<String>,
+    pub hook_timeout: Option<u64>,
+}
+
+impl TaskFile {
+    pub fn load(path: &Path) -> Result<Vec<Task>> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read task file at '{}'", path.display()))?;
+
+        let file = toml::from_str::<TaskFile>(&content)
+            .with_context(|| format!("Failed to parse task file at '{}'", path.display()))?;
+
+        let base_dir = fs::canonicalize(path)
+            .context("Failed to canonicalize the task file's path")?
+            .parent()
+            .context("Task file has no parent directory")?
+            .to_path_buf();
+
+        let tasks = file
+            .tasks
+            .into_iter()
+            .map(|(name, decl)| Task {
+                name,
+                shell: decl.shell,
+                cmd: decl.cmd,
+                start_dir: Some(match decl.start_dir {
+                    Some(start_dir) => base_dir.join(start_dir),
+                    None => base_dir.clone(),
+                }),
+                env: decl.env,
+                tags: decl.tags,
+                restart: decl.restart,
+                depends_on: decl.depends_on,
+                keep_running: decl.keep_running,
+                secret_env: vec![],
+                on_start: decl.on_start,
+                on_success: decl.on_success,
+                on_failure: decl.on_failure,
+                hook_timeout: decl.hook_timeout,
+            })
+            .collect();
+
+        sort_by_dependencies(tasks)
+    }
+}
+
+/// Order the tasks so each one comes after its dependencies, which must be declared in the file and not form a cycle
+fn sort_by_dependencies(tasks: Vec<Task>) -> Result<Vec<Task>> {
+    let mut pending = tasks
+        .into_iter()
+        .map(|task| (task.name.clone(), task))
+        .collect::<BTreeMap<_, _>>();
+
+    for task in pending.values() {
+        if let Some(unknown) = task
+            .depends_on
+            .iter()
+            .find(|dependency| !pending.contains_key(*dependency))
+        {
+            bail!(
+                "Task '{}' depends on '{unknown}', which is not declared in the task file",
+                task.name
+            );
+        }
+    }
+
+    let mut sorted = Vec::<Task>::with_capacity(pending.len());
+
+    while !pending.is_empty() {
+        let ready = pending
+            .values()
+            .filter(|task| {
+                task.depends_on
+                    .iter()
+                    .all(|dependency| !pending.contains_key(dependency))
+            })
+            .map(|task| task.name.clone())
+            .collect::<Vec<_>>();
+
+        if ready.is_empty() {
+            bail!(
+                "Tasks have circular dependencies: {}",
+                pending.keys().cloned().collect::<Vec<_>>().join(", ")
+            );
+        }
+
+        for name in ready {
+            sorted.extend(pending.remove(&name));
+        }
+    }
+
+    Ok(sorted)
+}
@@ -0,0 +1,401 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use nix::sys::signal::Signal;
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind},
+    layout::{Constraint, Layout},
+    style::{Color, Modifier, Style, Stylize},
+    text::Line,
+    widgets::{Block, Borders, Cell, Paragraph, Row, Table, TableState},
+    DefaultTerminal, Frame,
+};
+use time::OffsetDateTime;
+
+use crate::{
+    daemon::{DaemonClient, TaskFilter, TaskStatus, TaskSummary},
+    datetime::format_duration_ms,
+    process::{clock_ticks_per_sec, format_bytes, list_processes, process_group_usage},
+};
+
+static REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+static INPUT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+static SIGNALS: &[(char, Signal)] = &[
+    ('1', Signal::SIGHUP),
+    ('2', Signal::SIGINT),
+    ('3', Signal::SIGTERM),
+    ('4', Signal::SIGUSR1),
+    ('5', Signal::SIGUSR2),
+    ('6', Signal::SIGSTOP),
+    ('7', Signal::SIGCONT),
+];
+
+pub fn run_dashboard(client: DaemonClient) -> Result<()> {
+    let mut terminal = ratatui::init();
+    let result = Dashboard::new(client).run(&mut terminal);
+    ratatui::restore();
+    result
+}
+
+struct Dashboard {
+    client: DaemonClient,
+    tasks: Vec<TaskSummary>,
+    table_state: TableState,
+    usage: HashMap<u32, UsageSample>,
+    logs: Vec<String>,
+    logs_of: Option<String>,
+    /// Start time and restarts count of the task's run the logs belong to
+    logs_run: Option<(Option<OffsetDateTime>, usize)>,
+    /// Index of the next line to fetch, including the lines discarded because of the retention limit
+    logs_next: usize,
+    /// Number of lines scrolled up from the bottom of the logs, [`None`] when following the output
+    logs_scroll: Option<usize>,
+    choosing_signal: bool,
+    message: Option<String>,
+}
+
+struct UsageSample {
+    cpu_ticks: u64,
+    at: Instant,
+    cpu_percent: f64,
+    rss_bytes: u64,
+}
+
+impl Dashboard {
+    fn new(client: DaemonClient) -> Self {
+        Self {
+            client,
+            tasks: vec![],
+            table_state: TableState::default().with_selected(Some(0)),
+            usage: HashMap::new(),
+            logs: vec![],
+            logs_of: None,
+            logs_run: None,
+            logs_next: 0,
+            logs_scroll: None,
+            choosing_signal: false,
+            message: None,
+        }
+    }
+
+    fn run(mut self, terminal: &mut DefaultTerminal) -> Result<()> {
+        let mut last_refresh = None::<Instant>;
+
+        loop {
+            if last_refresh.is_none_or(|at| at.elapsed() >= REFRESH_INTERVAL) {
+                self.refresh()?;
+                last_refresh = Some(Instant::now());
+            }
+
+            terminal.draw(|frame| self.draw(frame))?;
+
+            if !event::poll(INPUT_POLL_INTERVAL)? {
+                continue;
+            }
+
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+
+            if self.choosing_signal {
+                self.choosing_signal = false;
+
+                if let KeyCode::Char(c) = key.code {
+                    if let Some((_, signal)) = SIGNALS.iter().find(|(key, _)| *key == c) {
+                        let signal = *signal;
+
+                        self.act_on_selected(format!("Sent {signal}"), |client, task_name| {
+                            client.signal(task_name, signal as i32)
+                        })?;
+                    }
+                }
+
+                continue;
+            }
+
+            self.message = None;
+
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+
+                KeyCode::Up => self.select(-1),
+                KeyCode::Down => self.select(1),
+
+                KeyCode::PageUp => {
+                    self.logs_scroll = Some(self.logs_scroll.unwrap_or(0) + 10);
+                }
+
+                KeyCode::PageDown => {
+                    self.logs_scroll = self
+                        .logs_scroll
+                        .and_then(|scroll| scroll.checked_sub(10))
+                        .filter(|scroll| *scroll > 0);
+                }
+
+                KeyCode::End | KeyCode::Char('f') => self.logs_scroll = None,
+
+                KeyCode::Char('k') => self
+                    .act_on_selected("Killed".to_owned(), |client, task_name| {
+                        client.kill(task_name)
+                    })?,
+
+                KeyCode::Char('r') => self
+                    .act_on_selected("Restarted".to_owned(), |client, task_name| {
+                        client.restart(task_name)
+                    })?,
+
+                KeyCode::Char('d') => self
+                    .act_on_selected("Removed".to_owned(), |client, task_name| {
+                        client.remove(task_name)
+                    })?,
+
+                KeyCode::Char('s') if self.selected().is_some() => self.choosing_signal = true,
+
+                _ => {}
+            }
+
+            // Reflect the changes immediatly
+            last_refresh = None;
+        }
+    }
+
+    fn selected(&self) -> Option<&TaskSummary> {
+        self.table_state
+            .selected()
+            .and_then(|index| self.tasks.get(index))
+    }
+
+    fn select(&mut self, offset: isize) {
+        if self.tasks.is_empty() {
+            return;
+        }
+
+        let current = self.table_state.selected().unwrap_or(0) as isize;
+        let selected = (current + offset).clamp(0, self.tasks.len() as isize - 1);
+
+        self.table_state.select(Some(selected as usize));
+    }
+
+    fn act_on_selected(
+        &mut self,
+        success: String,
+        action: impl FnOnce(&DaemonClient, String) -> Result<Result<(), String>>,
+    ) -> Result<()> {
+        let Some(task) = self.selected() else {
+            return Ok(());
+        };
+
+        let task_name = task.task.name.clone();
+
+        self.message = Some(match action(&self.client, task_name.clone())? {
+            Ok(()) => format!("{success} task '{task_name}'"),
+            Err(err) => format!("Error: {err}"),
+        });
+
+        Ok(())
+    }
+
+    fn refresh(&mut self) -> Result<()> {
+        self.tasks = self.client.list(TaskFilter::default())?;
+
+        if self.tasks.is_empty() {
+            self.table_state.select(None);
+        } else if self
+            .table_state
+            .selected()
+            .is_none_or(|i| i >= self.tasks.len())
+        {
+            self.table_state.select(Some(self.tasks.len() - 1));
+        }
+
+        self.refresh_usage();
+        self.refresh_logs()
+    }
+
+    fn refresh_usage(&mut self) {
+        let processes = list_processes();
+        let now = Instant::now();
+
+        let mut usage = HashMap::new();
+
+        for pid in self.tasks.iter().filter_map(|task| task.pid) {
+            // Tasks are spawned as process group leaders
+            let Some(current) = process_group_usage(&processes, pid) else {
+                continue;
+            };
+
+            let cpu_percent = match self.usage.get(&pid) {
+                Some(prev) => {
+                    let elapsed = now.duration_since(prev.at).as_secs_f64();
+                    let ticks = current.cpu_ticks.saturating_sub(prev.cpu_ticks) as f64;
+
+                    ticks / clock_ticks_per_sec() as f64 / elapsed * 100.0
+                }
+
+                None => 0.0,
+            };
+
+            usage.insert(
+                pid,
+                UsageSample {
+                    cpu_ticks: current.cpu_ticks,
+                    at: now,
+                    cpu_percent,
+                    rss_bytes: current.rss_bytes,
+                },
+            );
+        }
+
+        self.usage = usage;
+    }
+
+    fn refresh_logs(&mut self) -> Result<()> {
+        let selected = self.selected().map(|task| task.task.name.clone());
+
+        // Tasks restarted or replaced under the same name have a new output
+        let run = self.selected().map(|task| (task.started_at, task.restarts));
+
+        if selected != self.logs_of || run != self.logs_run {
+            self.logs.clear();
+            self.logs_scroll = None;
+            self.logs_of = selected;
+            self.logs_run = run;
+            self.logs_next = 0;
+        }
+
+        let Some(task_name) = self.logs_of.clone() else {
+            return Ok(());
+        };
+
+        // The task may have been removed since the list was fetched
+        match self.client.logs(task_name, self.logs_next)? {
+            Ok((first, new_lines)) => {
+                self.logs_next = first + new_lines.len();
+                self.logs.extend(new_lines);
+            }
+
+            Err(err) => self.message = Some(format!("Error: {err}")),
+        }
+
+        Ok(())
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [tasks_area, logs_area, footer_area] = Layout::vertical([
+            Constraint::Percentage(40),
+            Constraint::Min(3),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let rows = self.tasks.iter().map(|summary| {
+            let (status, color) = match &summary.status {
+                TaskStatus::NotStartedYet => ("Not started yet".to_owned(), Color::DarkGray),
+                TaskStatus::Running { child: _ } => ("Running".to_owned(), Color::Cyan),
+                TaskStatus::Success => ("Succeeded".to_owned(), Color::Green),
+                TaskStatus::Failed { code, .. } => (
+                    match code {
+                        Some(code) => format!("Failed ({code})"),
+                        None => "Failed".to_owned(),
+                    },
+                    Color::Red,
+                ),
+                TaskStatus::RunnerFailed { .. } => ("Runner failed".to_owned(), Color::Red),
+                TaskStatus::Orphaned => ("Orphaned".to_owned(), Color::Red),
+                TaskStatus::Exited => ("Exited".to_owned(), Color::DarkGray),
+            };
+
+            let usage = summary.pid.and_then(|pid| self.usage.get(&pid));
+
+            Row::new(vec![
+                Cell::from(summary.task.name.clone()).fg(Color::Yellow),
+                Cell::from(status).fg(color),
+                Cell::from(
+                    summary
+                        .duration_ms
+                        .map(format_duration_ms)
+                        .unwrap_or_default(),
+                ),
+                Cell::from(
+                    usage
+                        .map(|usage| format!("{:.1}%", usage.cpu_percent))
+                        .unwrap_or_default(),
+                ),
+                Cell::from(
+                    usage
+                        .map(|usage| format_bytes(usage.rss_bytes))
+                        .unwrap_or_default(),
+                ),
+                Cell::from(summary.task.cmd.clone()).fg(Color::Magenta),
+            ])
+        });
+
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Percentage(20),
+                Constraint::Length(16),
+                Constraint::Length(12),
+                Constraint::Length(8),
+                Constraint::Length(12),
+                Constraint::Fill(1),
+            ],
+        )
+        .header(
+            Row::new(["Name", "Status", "Duration", "CPU", "Memory", "Command"])
+                .add_modifier(Modifier::BOLD),
+        )
+        .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+        .block(Block::default().borders(Borders::ALL).title(" Tasks "));
+
+        frame.render_stateful_widget(table, tasks_area, &mut self.table_state);
+
+        let visible = logs_area.height.saturating_sub(2) as usize;
+        let end = self
+            .logs
+            .len()
+            .saturating_sub(self.logs_scroll.unwrap_or(0));
+        let start = end.saturating_sub(visible);
+
+        let logs_title = match (&self.logs_of, self.logs_scroll) {
+            (Some(task_name), None) => format!(" Logs of '{task_name}' (following) "),
+            (Some(task_name), Some(_)) => format!(" Logs of '{task_name}' "),
+            (None, _) => " Logs ".to_owned(),
+        };
+
+        let logs = Paragraph::new(
+            self.logs[start..end]
+                .iter()
+                .map(|line| Line::raw(line.as_str()))
+                .collect::<Vec<_>>(),
+        )
+        .block(Block::default().borders(Borders::ALL).title(logs_title));
+
+        frame.render_widget(logs, logs_area);
+
+        let footer = if self.choosing_signal {
+            format!(
+                "Signal to send: {}  (any other key to cancel)",
+                SIGNALS
+                    .iter()
+                    .map(|(key, signal)| format!("[{key}] {}", &signal.as_str()[3..]))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            )
+        } else if let Some(message) = &self.message {
+            message.clone()
+        } else {
+            "[↑/↓] select  [k] kill  [r] restart  [d] remove  [s] signal  [PgUp/PgDn/End] scroll logs  [q] quit".to_owned()
+        };
+
+        frame.render_widget(Paragraph::new(footer).fg(Color::DarkGray), footer_area);
+    }
+}
@@ -1,5 +1,11 @@
+use std::sync::RwLock;
+
+use anyhow::{Context, Result};
 use once_cell::sync::Lazy;
-use time::{OffsetDateTime, UtcOffset};
+use time::{
+    format_description::{self, OwnedFormatItem},
+    OffsetDateTime, UtcOffset,
+};
 
 use crate::warn;
 
@@ -11,6 +17,8 @@ static OFFSET: Lazy<UtcOffset> = Lazy::new(|| {
     })
 });
 
+static TIMESTAMP_FORMAT: Lazy<RwLock<Option<OwnedFormatItem>>> = Lazy::new(|| RwLock::new(None));
+
 pub fn get_now() -> OffsetDateTime {
     // OffsetDateTime::now_local()
     //     .context("Failed to determine current date/time")
@@ -26,3 +34,33 @@ pub fn get_now_second_precision() -> OffsetDateTime {
 pub fn second_precision(moment: OffsetDateTime) -> OffsetDateTime {
     moment.replace_nanosecond(0).unwrap()
 }
+
+/// Use a custom format for timestamps (see https://time-rs.github.io/book/api/format-description.html)
+pub fn set_timestamp_format(format: Option<&str>) -> Result<()> {
+    let format = format
+        .map(format_description::parse_owned::<2>)
+        .transpose()
+        .context("Invalid timestamp format")?;
+
+    *TIMESTAMP_FORMAT.write().unwrap() = format;
+
+    Ok(())
+}
+
+pub fn format_timestamp(moment: OffsetDateTime) -> String {
+    match &*TIMESTAMP_FORMAT.read().unwrap() {
+        Some(format) => moment.format(format).unwrap_or_else(|_| moment.to_string()),
+        None => moment.to_string(),
+    }
+}
+
+pub fn format_duration_ms(millis: u64) -> String {
+    let secs = millis / 1000;
+
+    match secs {
+        0 => format!("{millis}ms"),
+        1..=59 => format!("{secs}s"),
+        60..=3599 => format!("{}m {}s", secs / 60, secs % 60),
+        _ => format!("{}h {}m {}s", secs / 3600, (secs % 3600) / 60, secs % 60),
+    }
+}
@@ -2,6 +2,8 @@ use std::sync::atomic::AtomicBool;
 
 pub static PRINT_DEBUG_MESSAGES: AtomicBool = AtomicBool::new(false);
 pub static PRINT_MESSAGES_DATETIME: AtomicBool = AtomicBool::new(false);
+/// Print all messages to STDERR, used when the daemon runs in foreground
+pub static LOG_TO_STDERR: AtomicBool = AtomicBool::new(false);
 
 #[macro_export]
 macro_rules! _format {
@@ -10,13 +12,24 @@ macro_rules! _format {
         let mut msg = format!($message, $($params)*);
 
         if $crate::utils::logging::PRINT_MESSAGES_DATETIME.load(::std::sync::atomic::Ordering::Relaxed) {
-            msg = format!("[{}] {msg}", $crate::utils::datetime::get_now_second_precision());
+            msg = format!("[{}] {msg}", $crate::utils::datetime::format_timestamp($crate::utils::datetime::get_now_second_precision()));
         }
 
         msg.$color()
     }}
 }
 
+#[macro_export]
+macro_rules! _print {
+    ($message: expr) => {{
+        if $crate::utils::logging::LOG_TO_STDERR.load(::std::sync::atomic::Ordering::Relaxed) {
+            eprintln!("{}", $message);
+        } else {
+            println!("{}", $message);
+        }
+    }};
+}
+
 #[macro_export]
 macro_rules! error {
     ($message: tt, $($params: tt)*) => {{
@@ -50,7 +63,7 @@ macro_rules! warn {
 #[macro_export]
 macro_rules! info {
     ($message: tt, $($params: tt)*) => {{
-        println!("{}", $crate::_format!(bright_blue => $message, $($params)*));
+        $crate::_print!($crate::_format!(bright_blue => $message, $($params)*));
     }};
 
     ($message: tt) => {{
@@ -72,7 +85,7 @@ macro_rules! info_inline {
 #[macro_export]
 macro_rules! notice {
     ($message: tt, $($params: tt)*) => {{
-        println!("{}", $crate::_format!(bright_black => $message, $($params)*));
+        $crate::_print!($crate::_format!(bright_black => $message, $($params)*));
     }};
 
     ($message: tt) => {{
@@ -84,7 +97,7 @@ macro_rules! notice {
 macro_rules! debug {
     ($message: tt, $($params: tt)*) => {{
         if $crate::utils::logging::PRINT_DEBUG_MESSAGES.load(::std::sync::atomic::Ordering::Relaxed) {
-            println!("{}", $crate::_format!(bright_black => $message, $($params)*));
+            $crate::_print!($crate::_format!(bright_black => $message, $($params)*));
         }
     }};
 
@@ -96,7 +109,7 @@ macro_rules! debug {
 #[macro_export]
 macro_rules! success {
     ($message: tt, $($params: tt)*) => {{
-        println!("{}", $crate::_format!(bright_green => $message, $($params)*));
+        $crate::_print!($crate::_format!(bright_green => $message, $($params)*));
     }};
 
     ($message: tt) => {{
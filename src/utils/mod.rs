@@ -1,4 +1,6 @@
 pub mod datetime;
 pub mod logging;
 pub mod paging;
+pub mod private_file;
+pub mod process;
 pub mod sleep;
@@ -0,0 +1,29 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, ErrorKind, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::Path,
+};
+
+/// Atomically replace a file with a content only its owner can read, e.g. because it holds secrets
+///
+/// The content is written to a new temporary file first, so it never inherits the permissions of a leftover file
+/// and a crash can't leave a truncated file behind.
+pub fn write_private_file(path: &Path, content: &[u8]) -> io::Result<()> {
+    let mut tmp_file = path.as_os_str().to_owned();
+    tmp_file.push(".tmp");
+
+    match fs::remove_file(&tmp_file) {
+        Err(err) if err.kind() != ErrorKind::NotFound => return Err(err),
+        _ => {}
+    }
+
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&tmp_file)
+        .and_then(|mut file| file.write_all(content))?;
+
+    fs::rename(&tmp_file, path)
+}
@@ -0,0 +1,117 @@
+use std::fs;
+
+use nix::unistd::{sysconf, SysconfVar};
+use once_cell::sync::Lazy;
+
+static CLOCK_TICKS_PER_SEC: Lazy<u64> = Lazy::new(|| {
+    sysconf(SysconfVar::CLK_TCK)
+        .ok()
+        .flatten()
+        .map_or(100, |ticks| ticks as u64)
+});
+
+static PAGE_SIZE: Lazy<u64> = Lazy::new(|| {
+    sysconf(SysconfVar::PAGE_SIZE)
+        .ok()
+        .flatten()
+        .map_or(4096, |size| size as u64)
+});
+
+/// Fields of `/proc/<pid>/stat` we care about
+pub struct ProcStat {
+    pub pid: u32,
+    pub ppid: u32,
+    /// Process state, e.g. `R` for running or `Z` for zombie
+    pub state: char,
+    pub pgrp: u32,
+    /// CPU time consumed by the process (user + system), in clock ticks
+    pub cpu_ticks: u64,
+    pub rss_bytes: u64,
+    /// Time the process started after system boot, in clock ticks
+    pub start_time: u64,
+}
+
+pub fn read_proc_stat(pid: u32) -> Option<ProcStat> {
+    let stat = fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+
+    // The command name may contain spaces and parenthesis, so we split after its last closing parenthesis
+    let (_, fields) = stat.rsplit_once(')')?;
+    let fields = fields.split_whitespace().collect::<Vec<_>>();
+
+    // Field numbers (as documented in `proc(5)`) start at 1, and the PID and command name were skipped
+    let field = |index: usize| {
+        fields
+            .get(index - 3)
+            .and_then(|field| field.parse::<u64>().ok())
+    };
+
+    Some(ProcStat {
+        pid,
+        ppid: field(4)? as u32,
+        state: fields.first()?.chars().next()?,
+        pgrp: field(5)? as u32,
+        cpu_ticks: field(14)? + field(15)?,
+        rss_bytes: field(24)? * *PAGE_SIZE,
+        start_time: field(22)?,
+    })
+}
+
+pub fn list_processes() -> Vec<ProcStat> {
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return vec![];
+    };
+
+    entries
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<u32>().ok())
+        .filter_map(read_proc_stat)
+        .collect()
+}
+
+/// Resources used by all the processes of a process group
+#[derive(Clone, Copy)]
+pub struct GroupUsage {
+    pub cpu_ticks: u64,
+    pub rss_bytes: u64,
+}
+
+pub fn process_group_usage(processes: &[ProcStat], pgid: u32) -> Option<GroupUsage> {
+    let mut members = processes
+        .iter()
+        .filter(|process| process.pgrp == pgid)
+        .peekable();
+
+    members.peek()?;
+
+    Some(members.fold(
+        GroupUsage {
+            cpu_ticks: 0,
+            rss_bytes: 0,
+        },
+        |usage, process| GroupUsage {
+            cpu_ticks: usage.cpu_ticks + process.cpu_ticks,
+            rss_bytes: usage.rss_bytes + process.rss_bytes,
+        },
+    ))
+}
+
+pub fn clock_ticks_per_sec() -> u64 {
+    *CLOCK_TICKS_PER_SEC
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+
+    let mut value = bytes as f64;
+    let mut unit = 0;
+
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}